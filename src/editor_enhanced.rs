@@ -1,8 +1,18 @@
+use bevy::asset::AssetServerSettings;
 use bevy::prelude::*;
 use bevy::render::primitives::Aabb;
 use bevy_editor_pls::default_windows::add::*;
+use bevy_mod_picking::PickableBundle;
 use bevy_rapier3d::prelude::*;
 
+use crate::health::{Checkpoint, Hazard};
+use crate::level::{
+    Level, LevelCollider, LevelColliderShape, LevelEntity, LevelMaterial, LevelMesh,
+    LevelMeshSource, LevelRigidBody,
+};
+use crate::scene::LevelSettings;
+use crate::water::WaterVolume;
+
 #[derive(Default, Component, Reflect)]
 #[reflect(Component)]
 pub struct TmpColliderType {
@@ -45,7 +55,7 @@ impl From<&TmpRigidBodyType> for RigidBodyType {
 
 #[derive(Default, Component, Reflect)]
 #[reflect(Component)]
-pub struct TmpRigidBodyPosition(Vec3);
+pub struct TmpRigidBodyPosition(pub Vec3);
 
 impl From<&TmpRigidBodyPosition> for RigidBodyPosition {
     fn from(p: &TmpRigidBodyPosition) -> Self {
@@ -77,14 +87,19 @@ impl Plugin for EditorAdditionsPlugin {
         app.register_type::<TmpRigidBodyType>();
         app.register_type::<TmpRigidBodyPosition>();
         app.add_startup_system(editor_setup);
+        app.add_system(save_scene);
     }
 }
 
 pub fn editor_setup(mut editor: ResMut<bevy_editor_pls::Editor>) {
     let mut state = editor.window_state_mut::<AddWindow>().unwrap();
 
+    add_level_mesh(&mut state);
     add_collider(&mut state);
     add_rigit_body(&mut state);
+    state.add("Water", AddItem::component::<WaterVolume>());
+    state.add("Hazard", AddItem::component::<Hazard>());
+    state.add("Checkpoint", AddItem::component::<Checkpoint>());
 }
 
 pub fn collider_components(
@@ -113,6 +128,68 @@ pub fn collider_components(
     )
 }
 
+/// Gives the entity a primitive mesh whose description is saved with the level.
+fn insert_level_mesh(world: &mut World, entity: Entity, mesh: LevelMesh) {
+    let mesh_handle = world
+        .get_resource_mut::<Assets<Mesh>>()
+        .unwrap()
+        .add((&mesh).into());
+    let material = world
+        .get_resource_mut::<Assets<StandardMaterial>>()
+        .unwrap()
+        .add(StandardMaterial::default());
+    let mut e = world.entity_mut(entity);
+    let transform = e.get::<Transform>().cloned().unwrap_or_default();
+    e.insert_bundle(PbrBundle {
+        mesh: mesh_handle,
+        material,
+        transform,
+        ..Default::default()
+    })
+    .insert(LevelMeshSource(mesh))
+    .insert_bundle(PickableBundle::default());
+}
+
+pub fn add_level_mesh(state: &mut AddWindowState) {
+    state.add(
+        "Level",
+        AddItem::new("Box".into(), |world, entity| {
+            let mesh = LevelMesh::Box {
+                x: 1.0,
+                y: 1.0,
+                z: 1.0,
+            };
+            insert_level_mesh(world, entity, mesh)
+        }),
+    );
+    state.add(
+        "Level",
+        AddItem::new("Plane".into(), |world, entity| {
+            insert_level_mesh(world, entity, LevelMesh::Plane { size: 1.0 })
+        }),
+    );
+    state.add(
+        "Level",
+        AddItem::new("Sphere".into(), |world, entity| {
+            let mesh = LevelMesh::Sphere {
+                radius: 0.5,
+                subdivisions: 4,
+            };
+            insert_level_mesh(world, entity, mesh)
+        }),
+    );
+    state.add(
+        "Level",
+        AddItem::new("Capsule".into(), |world, entity| {
+            let mesh = LevelMesh::Capsule {
+                radius: 0.5,
+                depth: 1.0,
+            };
+            insert_level_mesh(world, entity, mesh)
+        }),
+    );
+}
+
 pub fn add_collider(state: &mut AddWindowState) {
     state.add("PhysicsTmp", AddItem::component::<TmpColliderType>());
 
@@ -215,3 +292,150 @@ pub fn add_rigit_body(state: &mut AddWindowState) {
         }),
     );
 }
+
+/// Writes every editable entity back to the level file on Ctrl+S. Nothing is
+/// written if a mesh was not added through the "Level" add items or a material
+/// uses textures, since the level can not describe them.
+#[allow(clippy::type_complexity)]
+pub fn save_scene(
+    keys: Res<Input<KeyCode>>,
+    settings: Res<LevelSettings>,
+    asset_settings: Res<AssetServerSettings>,
+    materials: Res<Assets<StandardMaterial>>,
+    query: Query<
+        (
+            Entity,
+            Option<&Name>,
+            &Transform,
+            Option<&Aabb>,
+            Option<&LevelMeshSource>,
+            Option<&Handle<StandardMaterial>>,
+            Option<&TmpColliderType>,
+            Option<&ColliderShapeComponent>,
+            Option<&TmpRigidBodyType>,
+            Option<&TmpRigidBodyPosition>,
+            Option<&WaterVolume>,
+            Option<&Hazard>,
+            Option<&Checkpoint>,
+        ),
+        Or<(
+            With<TmpColliderType>,
+            With<TmpRigidBodyType>,
+            With<LevelMeshSource>,
+        )>,
+    >,
+) {
+    let ctrl = keys.pressed(KeyCode::LControl) || keys.pressed(KeyCode::RControl);
+    if !(ctrl && keys.just_pressed(KeyCode::S)) {
+        return;
+    }
+
+    let mut level = Level::default();
+    let mut undescribed = Vec::new();
+    let mut textured = Vec::new();
+    for (
+        entity,
+        name,
+        transform,
+        aabb,
+        mesh_source,
+        material,
+        collider_type,
+        collider_shape,
+        body_type,
+        body_position,
+        water,
+        hazard,
+        checkpoint,
+    ) in query.iter()
+    {
+        let name = match name {
+            Some(name) => name.as_str().to_string(),
+            None => format!("Entity {}", entity.id()),
+        };
+
+        let mesh = match mesh_source {
+            Some(source) => source.0.clone(),
+            None => {
+                undescribed.push(name);
+                continue;
+            }
+        };
+
+        let material = match material.and_then(|handle| materials.get(handle)) {
+            Some(material) => match LevelMaterial::from_material(material) {
+                Some(material) => material,
+                None => {
+                    textured.push(name);
+                    continue;
+                }
+            },
+            None => LevelMaterial::default(),
+        };
+
+        let collider = match collider_type {
+            Some(collider_type) => {
+                let shape = match (collider_shape, aabb) {
+                    (Some(shape), _) => LevelColliderShape::from_shape(shape),
+                    (None, Some(aabb)) => Some(LevelColliderShape::Cuboid {
+                        half_extents: transform.scale * aabb.half_extents,
+                    }),
+                    (None, None) => None,
+                };
+                match shape {
+                    Some(shape) => Some(LevelCollider {
+                        shape,
+                        sensor: ColliderType::from(collider_type) == ColliderType::Sensor,
+                    }),
+                    None => {
+                        warn!("{:?}: collider shape can not be saved", name);
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+
+        let rigid_body = body_type.map(|body_type| LevelRigidBody {
+            body_type: RigidBodyType::from(body_type).into(),
+            position: body_position.map(|p| p.0),
+        });
+
+        level.entities.push(LevelEntity {
+            name,
+            mesh,
+            transform: transform.into(),
+            material,
+            collider,
+            rigid_body,
+            water: water.cloned(),
+            hazard: hazard.cloned(),
+            checkpoint: checkpoint.is_some(),
+        });
+    }
+
+    if !undescribed.is_empty() {
+        error!(
+            "level not saved, these meshes were not added as level meshes: {:?}",
+            undescribed
+        );
+        return;
+    }
+    if !textured.is_empty() {
+        error!(
+            "level not saved, these materials use textures or an alpha mask: {:?}",
+            textured
+        );
+        return;
+    }
+
+    let path = settings.file_path(&asset_settings);
+    match level.save(&path) {
+        Ok(()) => info!(
+            "saved {} entities to {}",
+            level.entities.len(),
+            path.display()
+        ),
+        Err(e) => error!("failed to save level: {}", e),
+    }
+}
@@ -0,0 +1,128 @@
+use bevy::ecs::schedule::ShouldRun;
+use bevy::prelude::*;
+use bevy::transform::TransformSystem;
+use bevy_rapier3d::physics::{PhysicsStages, PhysicsSystems};
+use bevy_rapier3d::prelude::*;
+
+use crate::player::ControllerTime;
+
+/// Steps physics, and the systems added to `PhysicsStages::StepWorld`, at a
+/// fixed rate. Must be added after `RapierPhysicsPlugin`.
+pub struct FixedStepPlugin;
+
+impl Plugin for FixedStepPlugin {
+    fn build(&self, app: &mut App) {
+        let step = FixedStep::default();
+        // one physics step of `dt` per run of the step stage
+        if let Some(mut config) = app.world.get_resource_mut::<RapierConfiguration>() {
+            config.timestep_mode = TimestepMode::FixedTimestep;
+        }
+        if let Some(mut params) = app.world.get_resource_mut::<IntegrationParameters>() {
+            params.dt = step.dt;
+        }
+        app.insert_resource(step)
+            .stage(PhysicsStages::StepWorld, |stage: &mut SystemStage| {
+                stage.set_run_criteria(fixed_step_criteria)
+            })
+            .add_system_to_stage(
+                PhysicsStages::StepWorld,
+                store_previous_positions.before(PhysicsSystems::StepWorld),
+            )
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                interpolate_transforms
+                    .label(FixedStepSystem::Interpolate)
+                    .before(TransformSystem::TransformPropagate),
+            );
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemLabel)]
+pub enum FixedStepSystem {
+    Interpolate,
+}
+
+#[derive(Debug)]
+pub struct FixedStep {
+    pub dt: f32,
+    /// Simulated seconds, advanced by `dt` on every step
+    pub elapsed: f64,
+    /// Most steps run in one frame, the frame time past them is dropped so a
+    /// slow frame does not make the next ones slower
+    pub max_steps: u32,
+    accumulator: f32,
+    looping: bool,
+}
+
+impl Default for FixedStep {
+    fn default() -> Self {
+        Self {
+            dt: 1.0 / 60.0,
+            elapsed: 0.0,
+            max_steps: 5,
+            accumulator: 0.0,
+            looping: false,
+        }
+    }
+}
+
+impl FixedStep {
+    /// Part of a step the frame is past the last step, in [0, 1].
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator / self.dt).clamp(0.0, 1.0)
+    }
+}
+
+/// Runs the stage once for every `dt` of frame time, so controller forces and
+/// physics steps stay paired whatever the frame rate.
+pub fn fixed_step_criteria(clock: Res<ControllerTime>, mut step: ResMut<FixedStep>) -> ShouldRun {
+    if !step.looping {
+        step.accumulator = (step.accumulator + clock.delta).min(step.dt * step.max_steps as f32);
+    }
+    if step.accumulator >= step.dt {
+        step.accumulator -= step.dt;
+        step.elapsed += step.dt as f64;
+        step.looping = true;
+        ShouldRun::YesAndCheckAgain
+    } else {
+        step.looping = false;
+        ShouldRun::No
+    }
+}
+
+/// Renders a rigid body between its last two physics positions. Replaces
+/// `RigidBodyPositionSync` on the entity.
+#[derive(Debug, Default, Component)]
+pub struct InterpolatedTransform {
+    pub previous: Option<(Vec3, Quat)>,
+}
+
+pub fn store_previous_positions(
+    mut bodies: Query<(&RigidBodyPositionComponent, &mut InterpolatedTransform)>,
+) {
+    for (rb_pos, mut interpolated) in bodies.iter_mut() {
+        interpolated.previous = Some((
+            rb_pos.position.translation.vector.into(),
+            rb_pos.position.rotation.into(),
+        ));
+    }
+}
+
+pub fn interpolate_transforms(
+    step: Res<FixedStep>,
+    mut bodies: Query<(
+        &RigidBodyPositionComponent,
+        &InterpolatedTransform,
+        &mut Transform,
+    )>,
+) {
+    let alpha = step.alpha();
+    for (rb_pos, interpolated, mut transform) in bodies.iter_mut() {
+        let translation: Vec3 = rb_pos.position.translation.vector.into();
+        let rotation: Quat = rb_pos.position.rotation.into();
+        let (previous_translation, previous_rotation) =
+            interpolated.previous.unwrap_or((translation, rotation));
+        transform.translation = previous_translation.lerp(translation, alpha);
+        transform.rotation = previous_rotation.slerp(rotation, alpha);
+    }
+}
@@ -1,4 +1,11 @@
+use std::collections::HashMap;
+
 use bevy::prelude::*;
+use bevy::transform::TransformSystem;
+
+use crate::fixed_step::FixedStepSystem;
+use crate::input_map::{Action, ActionInput, LocalInputMap};
+use crate::player::ControllerTime;
 
 #[derive(Debug, Component)]
 pub struct FollowerTarget;
@@ -11,8 +18,25 @@ pub enum FollowerType {
 
 #[derive(Debug, Component)]
 pub struct Follower {
-    pub id: u32,
     pub f_type: FollowerType,
+    /// Entity with `FollowerTarget` this follower tracks
+    pub target: Entity,
+}
+
+impl Follower {
+    /// Camera transform at `position` around a target at `target_position`.
+    pub fn transform(
+        &self,
+        config: &FollowerConfig,
+        position: &Position,
+        target_position: Vec3,
+    ) -> Transform {
+        let transform = position.to_transform(target_position);
+        match self.f_type {
+            FollowerType::Follow => transform,
+            FollowerType::LookAt => transform.looking_at(target_position, config.up_direction),
+        }
+    }
 }
 
 #[derive(Debug, Default, Component)]
@@ -65,6 +89,23 @@ pub struct FollowerPosition {
     pub current_position: Position,
 }
 
+impl FollowerPosition {
+    /// Ends a running transition at its target and moves the camera straight
+    /// to its place around `target_position`, instead of lerping there.
+    pub fn snap(
+        &mut self,
+        follower: &Follower,
+        config: &FollowerConfig,
+        transform: &mut Transform,
+        target_position: Vec3,
+    ) {
+        if let PositionState::Transition(new_pos) = std::mem::take(&mut self.position_state) {
+            self.current_position = new_pos.position;
+        }
+        *transform = follower.transform(config, &self.current_position, target_position);
+    }
+}
+
 #[derive(Debug, Default, Component)]
 pub struct FollowerConfig {
     pub transition_time: f64,
@@ -73,63 +114,69 @@ pub struct FollowerConfig {
 
 #[derive(Debug, Default, Component)]
 pub struct FollowerController {
-    pub follower_id: u32,
     pub rotation_speed: f32,
-    pub rotate_right: bool,
-    pub rotate_left: bool,
-    pub rotate_up: bool,
-    pub rotate_down: bool,
+    /// Rotation input in [-1, 1], positive is right
+    pub yaw: f32,
+    /// Rotation input in [-1, 1], positive is up
+    pub pitch: f32,
 }
 
 impl FollowerController {
     pub fn rotation_horizontal(&self) -> f32 {
-        if self.rotate_right {
-            self.rotation_speed
-        } else if self.rotate_left {
-            -self.rotation_speed
-        } else {
-            0.0
-        }
+        self.yaw * self.rotation_speed
     }
     pub fn rotation_vertical(&self) -> f32 {
-        if self.rotate_up {
-            -self.rotation_speed
-        } else if self.rotate_down {
-            self.rotation_speed
-        } else {
-            0.0
-        }
+        -self.pitch * self.rotation_speed
     }
 }
 
-pub fn update_controller(keys: Res<Input<KeyCode>>, mut controller: ResMut<FollowerController>) {
-    controller.rotate_left = keys.pressed(KeyCode::Left);
-    controller.rotate_right = keys.pressed(KeyCode::Right);
-    controller.rotate_up = keys.pressed(KeyCode::Up);
-    controller.rotate_down = keys.pressed(KeyCode::Down);
+/// Reads camera input through the input map of each follower's target, so
+/// every local player turns only its own camera.
+pub fn update_controller(
+    input: ActionInput,
+    mut controllers: Query<(&Follower, &mut FollowerController)>,
+    local_maps: Query<&LocalInputMap>,
+) {
+    for (follower, mut controller) in controllers.iter_mut() {
+        let input = input.mapped(local_maps.get(follower.target).ok());
+        controller.yaw = input.axis(Action::CameraYaw);
+        controller.pitch = input.axis(Action::CameraPitch);
+    }
 }
 
+/// Runs on the controller clock so replays turn the camera the same way, and
+/// after the targets are interpolated so the camera moves with them.
+#[allow(clippy::type_complexity)]
 pub fn update_followers(
-    time: Res<Time>,
-    controller: Res<FollowerController>,
+    time: Res<ControllerTime>,
     mut query: QuerySet<(
-        QueryState<&Transform, With<FollowerTarget>>,
+        QueryState<(Entity, &Transform), With<FollowerTarget>>,
         QueryState<(
             &Follower,
             &FollowerConfig,
+            Option<&FollowerController>,
             &mut FollowerPosition,
             &mut Transform,
         )>,
     )>,
 ) {
-    let player_position = query.q0().single().translation;
+    let targets: HashMap<Entity, Vec3> = query
+        .q0()
+        .iter()
+        .map(|(entity, transform)| (entity, transform.translation))
+        .collect();
+
+    for (follower, config, controller, mut position, mut transform) in query.q1().iter_mut() {
+        let player_position = match targets.get(&follower.target) {
+            Some(position) => *position,
+            None => continue,
+        };
 
-    for (follower, config, mut position, mut transform) in query.q1().iter_mut() {
         let mut final_pos = None;
         match &position.position_state {
             PositionState::Normal => {
-                if follower.id == controller.follower_id {
-                    let delta = time.delta().as_secs_f32();
+                if let Some(controller) = controller {
+                    let delta = time.delta;
                     let h_angle = controller.rotation_horizontal() * delta;
                     let v_angle = controller.rotation_vertical() * delta;
 
@@ -143,19 +190,11 @@ pub fn update_followers(
                         .update(h_angle, up, v_angle, right);
                 }
 
-                let new_transform = position
-                    .current_position
-                    .to_transform(player_position.clone());
-
-                *transform = match follower.f_type {
-                    FollowerType::Follow => new_transform,
-                    FollowerType::LookAt => {
-                        new_transform.looking_at(player_position, config.up_direction)
-                    }
-                };
+                *transform =
+                    follower.transform(config, &position.current_position, player_position);
             }
             PositionState::Transition(new_pos) => {
-                let now = time.seconds_since_startup();
+                let now = time.elapsed;
                 let mut delta = (now - new_pos.transiton_start) / config.transition_time;
 
                 if delta >= 1.0 {
@@ -166,14 +205,7 @@ pub fn update_followers(
                     .current_position
                     .transition_to(delta as f32, &new_pos.position);
 
-                let new_transform = intermediate_pos.to_transform(player_position.clone());
-
-                *transform = match follower.f_type {
-                    FollowerType::Follow => new_transform,
-                    FollowerType::LookAt => {
-                        new_transform.looking_at(player_position, config.up_direction)
-                    }
-                };
+                *transform = follower.transform(config, &intermediate_pos, player_position);
 
                 if delta == 1.0 {
                     final_pos = Some(intermediate_pos);
@@ -191,7 +223,11 @@ pub struct FollowCameraPlugin;
 
 impl Plugin for FollowCameraPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(update_controller)
-            .add_system(update_followers);
+        app.add_system(update_controller).add_system_to_stage(
+            CoreStage::PostUpdate,
+            update_followers
+                .after(FixedStepSystem::Interpolate)
+                .before(TransformSystem::TransformPropagate),
+        );
     }
 }
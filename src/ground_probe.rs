@@ -0,0 +1,191 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use crate::health::Hazard;
+use crate::player::{Player, PlayerControllerSettings};
+
+/// Radius of the circle of ground rays around the center one.
+pub const GROUND_RAY_RADIUS: f32 = 0.4;
+pub const GROUND_RAY_COUNT: usize = 4;
+pub const WALL_RAY_COUNT: usize = 8;
+
+#[derive(Debug, Clone, Copy)]
+pub struct GroundHit {
+    /// Entity hit by the closest ray
+    pub entity: Entity,
+    /// Average distance of the averaged hits
+    pub distance: f32,
+    /// Average normal of the averaged hits
+    pub normal: Vec3,
+    /// Average contact point of the averaged hits
+    pub point: Vec3,
+}
+
+impl GroundHit {
+    /// Averages the hits within `tolerance` of the closest one, so rays that
+    /// reach past an edge to the ground far below are left out.
+    pub fn average(hits: &[GroundHit], tolerance: f32) -> Option<Self> {
+        let closest = hits
+            .iter()
+            .min_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap())?;
+        let near: Vec<&GroundHit> = hits
+            .iter()
+            .filter(|hit| hit.distance <= closest.distance + tolerance)
+            .collect();
+        let count = near.len() as f32;
+        let sum = near.iter().fold((0.0, Vec3::ZERO, Vec3::ZERO), |acc, hit| {
+            (acc.0 + hit.distance, acc.1 + hit.normal, acc.2 + hit.point)
+        });
+        Some(Self {
+            entity: closest.entity,
+            distance: sum.0 / count,
+            normal: sum.1.normalize_or_zero(),
+            point: sum.2 / count,
+        })
+    }
+}
+
+/// Downward rays at the center and on a circle around it.
+#[derive(Debug, Default, Component)]
+pub struct GroundProbe {
+    pub hit: Option<GroundHit>,
+}
+
+/// Upward ray from the center.
+#[derive(Debug, Default, Component)]
+pub struct CeilingProbe {
+    /// Distance from the center to the closest hit above it
+    pub distance: Option<f32>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct WallHit {
+    pub entity: Entity,
+    pub distance: f32,
+    pub normal: Vec3,
+}
+
+impl From<GroundHit> for WallHit {
+    fn from(hit: GroundHit) -> Self {
+        Self {
+            entity: hit.entity,
+            distance: hit.distance,
+            normal: hit.normal,
+        }
+    }
+}
+
+/// Horizontal rays evenly spread around the center.
+#[derive(Debug, Default, Component)]
+pub struct WallProbe {
+    /// Closest hit of all wall rays, at any distance
+    pub hit: Option<WallHit>,
+}
+
+/// A forward ray at `ledge_chest_height` finding the wall below a ledge, and
+/// a downward ray from `ledge_top_height`, `ledge_reach` ahead, finding its top.
+#[derive(Debug, Default, Component)]
+pub struct LedgeProbe {
+    pub wall: Option<WallHit>,
+    pub top: Option<GroundHit>,
+}
+
+/// Casts a ray from `origin` along the unit `dir`, returning the closest hit
+/// `filter` accepts.
+fn cast(
+    pipeline: &QueryPipeline,
+    colliders: &QueryPipelineColliderComponentsSet,
+    filter: &dyn Fn(ColliderHandle) -> bool,
+    origin: Vec3,
+    dir: Vec3,
+) -> Option<GroundHit> {
+    let ray = Ray::new(origin.into(), dir.into());
+    let (handle, intersection) = pipeline.cast_ray_and_get_normal(
+        colliders,
+        &ray,
+        f32::MAX,
+        true,
+        InteractionGroups::all(),
+        Some(filter),
+    )?;
+    Some(GroundHit {
+        entity: handle.entity(),
+        distance: intersection.toi,
+        normal: intersection.normal.into(),
+        point: origin + dir * intersection.toi,
+    })
+}
+
+/// Casts every probe from the physics position of its player, so they see
+/// the world as the step about to run does. The rays turn with the player,
+/// its forward is the local x axis.
+#[allow(clippy::type_complexity)]
+pub fn update_probes(
+    pipeline: Res<QueryPipeline>,
+    collider_query: QueryPipelineColliderComponentsQuery,
+    collider_types: Query<&ColliderTypeComponent>,
+    hazards: Query<(), With<Hazard>>,
+    mut players: Query<
+        (
+            Entity,
+            &PlayerControllerSettings,
+            &RigidBodyPositionComponent,
+            &mut GroundProbe,
+            &mut CeilingProbe,
+            &mut WallProbe,
+            &mut LedgeProbe,
+        ),
+        With<Player>,
+    >,
+) {
+    let colliders = QueryPipelineColliderComponentsSet(&collider_query);
+    for (entity, settings, rb_pos, mut ground, mut ceiling, mut wall, mut ledge) in
+        players.iter_mut()
+    {
+        // the player itself, sensors, and hazards which the player has to sink onto to touch
+        let filter = |handle: ColliderHandle| {
+            let hit = handle.entity();
+            hit != entity
+                && hazards.get(hit).is_err()
+                && collider_types
+                    .get(hit)
+                    .map_or(true, |t| t.0 == ColliderType::Solid)
+        };
+        let position: Vec3 = rb_pos.position.translation.vector.into();
+        let rotation: Quat = rb_pos.position.rotation.into();
+        let cast_local = |offset: Vec3, dir: Vec3| {
+            cast(
+                &pipeline,
+                &colliders,
+                &filter,
+                position + rotation * offset,
+                rotation * dir,
+            )
+        };
+
+        let ground_hits: Vec<GroundHit> = std::iter::once(Vec3::ZERO)
+            .chain((0..GROUND_RAY_COUNT).map(|i| {
+                let angle = i as f32 / GROUND_RAY_COUNT as f32 * std::f32::consts::TAU;
+                Vec3::new(angle.cos(), 0.0, angle.sin()) * GROUND_RAY_RADIUS
+            }))
+            .filter_map(|offset| cast_local(offset, -Vec3::Y))
+            .collect();
+        ground.hit = GroundHit::average(&ground_hits, settings.ground_tolerance);
+
+        ceiling.distance = cast_local(Vec3::ZERO, Vec3::Y).map(|hit| hit.distance);
+
+        wall.hit = (0..WALL_RAY_COUNT)
+            .filter_map(|i| {
+                let angle = i as f32 / WALL_RAY_COUNT as f32 * std::f32::consts::TAU;
+                cast_local(Vec3::ZERO, Quat::from_rotation_y(angle) * -Vec3::Z)
+            })
+            .min_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap())
+            .map(WallHit::from);
+
+        ledge.wall = cast_local(Vec3::Y * settings.ledge_chest_height, Vec3::X).map(WallHit::from);
+        ledge.top = cast_local(
+            Vec3::new(settings.ledge_reach, settings.ledge_top_height, 0.0),
+            -Vec3::Y,
+        );
+    }
+}
@@ -0,0 +1,207 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::fixed_step::InterpolatedTransform;
+use crate::follower::{Follower, FollowerConfig, FollowerPosition};
+use crate::player::{
+    Player, PlayerCamera, PlayerDash, PlayerInputLatch, PlayerMovement, PlayerMovementState,
+};
+use crate::stance::PlayerStance;
+use crate::water::InWater;
+
+pub struct HealthPlugin;
+
+impl Plugin for HealthPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<KillPlane>()
+            .register_type::<Health>()
+            .register_type::<Hazard>()
+            .register_type::<Checkpoint>()
+            .add_event::<PlayerDamaged>()
+            .add_event::<PlayerRespawned>()
+            .add_system(damage_from_hazards.label(HealthSystem::Damage))
+            .add_system(reach_checkpoints.label(HealthSystem::Damage))
+            .add_system(respawn_players.after(HealthSystem::Damage));
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemLabel)]
+pub enum HealthSystem {
+    Damage,
+}
+
+#[derive(Debug, Default, Component, Reflect)]
+#[reflect(Component)]
+pub struct Health {
+    pub current: f32,
+    pub max: f32,
+}
+
+impl Health {
+    pub fn new(max: f32) -> Self {
+        Self { current: max, max }
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.current <= 0.0
+    }
+}
+
+/// Collider that damages the player when they touch it.
+#[derive(Debug, Default, Clone, Component, Reflect, Serialize, Deserialize)]
+#[reflect(Component)]
+pub struct Hazard {
+    pub damage: f32,
+}
+
+/// Sensor collider that moves the player's respawn point onto itself.
+#[derive(Debug, Default, Component, Reflect)]
+#[reflect(Component)]
+pub struct Checkpoint;
+
+/// Where the player respawns, its spawn point until a checkpoint is reached.
+#[derive(Debug, Component)]
+pub struct RespawnPoint(pub Vec3);
+
+/// Players below this height respawn.
+#[derive(Debug)]
+pub struct KillPlane {
+    pub height: f32,
+}
+
+impl Default for KillPlane {
+    fn default() -> Self {
+        Self { height: -20.0 }
+    }
+}
+
+/// Sent for every hazard contact, for hit feedback outside the controller.
+#[allow(dead_code)]
+pub struct PlayerDamaged {
+    pub player: Entity,
+    pub hazard: Entity,
+    pub amount: f32,
+}
+
+/// Sent after a player was moved back to its respawn point.
+#[allow(dead_code)]
+pub struct PlayerRespawned {
+    pub player: Entity,
+}
+
+pub fn damage_from_hazards(
+    mut contacts: EventReader<ContactEvent>,
+    mut damaged: EventWriter<PlayerDamaged>,
+    hazards: Query<&Hazard>,
+    mut players: Query<&mut Health, With<Player>>,
+) {
+    for event in contacts.iter() {
+        let (a, b) = match event {
+            ContactEvent::Started(a, b) => (a.entity(), b.entity()),
+            ContactEvent::Stopped(..) => continue,
+        };
+        for (hazard, player) in [(a, b), (b, a)] {
+            if let (Ok(h), Ok(mut health)) = (hazards.get(hazard), players.get_mut(player)) {
+                health.current -= h.damage;
+                damaged.send(PlayerDamaged {
+                    player,
+                    hazard,
+                    amount: h.damage,
+                });
+            }
+        }
+    }
+}
+
+pub fn reach_checkpoints(
+    mut intersections: EventReader<IntersectionEvent>,
+    checkpoints: Query<&GlobalTransform, With<Checkpoint>>,
+    mut players: Query<&mut RespawnPoint, With<Player>>,
+) {
+    for event in intersections.iter().filter(|e| e.intersecting) {
+        let (a, b) = (event.collider1.entity(), event.collider2.entity());
+        for (checkpoint, player) in [(a, b), (b, a)] {
+            if let (Ok(transform), Ok(mut respawn)) =
+                (checkpoints.get(checkpoint), players.get_mut(player))
+            {
+                respawn.0 = transform.translation;
+            }
+        }
+    }
+}
+
+/// Respawns dead players and players below the kill plane with a fresh
+/// controller state, snapping their transform and camera to the respawn point.
+#[allow(clippy::type_complexity)]
+pub fn respawn_players(
+    kill_plane: Res<KillPlane>,
+    mut respawned: EventWriter<PlayerRespawned>,
+    mut players: Query<
+        (
+            Entity,
+            &mut Health,
+            &RespawnPoint,
+            &mut RigidBodyPositionComponent,
+            &mut RigidBodyVelocityComponent,
+            &mut PlayerMovement,
+            &mut PlayerMovementState,
+            &mut PlayerDash,
+            &mut PlayerStance,
+            &mut PlayerInputLatch,
+            &mut InWater,
+            &mut InterpolatedTransform,
+            Option<&PlayerCamera>,
+        ),
+        With<Player>,
+    >,
+    mut cameras: Query<(
+        &Follower,
+        &FollowerConfig,
+        &mut FollowerPosition,
+        &mut Transform,
+    )>,
+) {
+    for (
+        entity,
+        mut health,
+        respawn,
+        mut rb_pos,
+        mut rb_vel,
+        mut movement,
+        mut state,
+        mut dash,
+        mut stance,
+        mut latch,
+        mut in_water,
+        mut interpolated,
+        player_camera,
+    ) in players.iter_mut()
+    {
+        let fell = rb_pos.position.translation.y < kill_plane.height;
+        if !health.is_dead() && !fell {
+            continue;
+        }
+
+        *rb_pos = RigidBodyPositionComponent(respawn.0.into());
+        rb_vel.linvel = Vector::zeros();
+        rb_vel.angvel = Vector::zeros();
+        health.current = health.max;
+        *movement = PlayerMovement::default();
+        *state = PlayerMovementState::default();
+        *dash = PlayerDash::default();
+        *latch = PlayerInputLatch::default();
+        // the collider sizes stay, they describe the collider as it is built
+        stance.crouch = 0.0;
+        stance.sprint = 0.0;
+        in_water.volumes.clear();
+        // no interpolation from the position the player died at
+        interpolated.previous = None;
+        if let Some((follower, config, mut position, mut transform)) =
+            player_camera.and_then(|c| cameras.get_mut(c.0).ok())
+        {
+            position.snap(follower, config, &mut transform, respawn.0);
+        }
+        respawned.send(PlayerRespawned { player: entity });
+    }
+}
@@ -0,0 +1,476 @@
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+use bevy::asset::{AssetLoader, LoadContext, LoadedAsset};
+use bevy::ecs::system::SystemParam;
+use bevy::input::gamepad::{GamepadEvent, GamepadEventType};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::utils::BoxedFuture;
+use serde::{Deserialize, Serialize};
+
+pub struct InputMapPlugin;
+
+impl Plugin for InputMapPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<InputMap>()
+            .init_asset_loader::<InputMapLoader>()
+            .init_resource::<InputMapSettings>()
+            .init_resource::<InputMap>()
+            .add_startup_system(load_input_map)
+            .add_system(apply_input_map)
+            .add_system_to_stage(CoreStage::PreUpdate, track_gamepad);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Action {
+    MoveForward,
+    MoveRight,
+    Jump,
+    CameraYaw,
+    CameraPitch,
+    Crouch,
+    Sprint,
+    Dash,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum InputBinding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    GamepadButton(GamepadButtonType),
+}
+
+/// Inputs bound to an action. Button actions only use `positive`,
+/// axis actions are `positive` minus `negative` plus any analog `axes`.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ActionBinding {
+    pub positive: Vec<InputBinding>,
+    pub negative: Vec<InputBinding>,
+    pub axes: Vec<GamepadAxisType>,
+}
+
+impl ActionBinding {
+    pub fn button(inputs: &[InputBinding]) -> Self {
+        Self {
+            positive: inputs.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    pub fn axis(
+        positive: &[InputBinding],
+        negative: &[InputBinding],
+        axes: &[GamepadAxisType],
+    ) -> Self {
+        Self {
+            positive: positive.to_vec(),
+            negative: negative.to_vec(),
+            axes: axes.to_vec(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TypeUuid)]
+#[uuid = "2f6c8e1a-4b7d-4e39-a5c2-7d18e9f03b56"]
+pub struct InputMap {
+    pub bindings: HashMap<Action, ActionBinding>,
+    /// Gamepad used for gamepad bindings, picked up on connection.
+    #[serde(skip)]
+    pub gamepad: Option<Gamepad>,
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        use InputBinding::*;
+
+        let mut bindings = HashMap::new();
+        bindings.insert(
+            Action::MoveForward,
+            ActionBinding::axis(
+                &[Key(KeyCode::W)],
+                &[Key(KeyCode::S)],
+                &[GamepadAxisType::LeftStickY],
+            ),
+        );
+        bindings.insert(
+            Action::MoveRight,
+            ActionBinding::axis(
+                &[Key(KeyCode::D)],
+                &[Key(KeyCode::A)],
+                &[GamepadAxisType::LeftStickX],
+            ),
+        );
+        bindings.insert(
+            Action::Jump,
+            ActionBinding::button(&[Key(KeyCode::Space), GamepadButton(GamepadButtonType::South)]),
+        );
+        bindings.insert(
+            Action::Crouch,
+            ActionBinding::button(&[Key(KeyCode::C), GamepadButton(GamepadButtonType::East)]),
+        );
+        bindings.insert(
+            Action::Sprint,
+            ActionBinding::button(&[
+                Key(KeyCode::LShift),
+                GamepadButton(GamepadButtonType::LeftThumb),
+            ]),
+        );
+        bindings.insert(
+            Action::Dash,
+            ActionBinding::button(&[Key(KeyCode::E), GamepadButton(GamepadButtonType::West)]),
+        );
+        bindings.insert(
+            Action::CameraYaw,
+            ActionBinding::axis(
+                &[Key(KeyCode::Right)],
+                &[Key(KeyCode::Left)],
+                &[GamepadAxisType::RightStickX],
+            ),
+        );
+        bindings.insert(
+            Action::CameraPitch,
+            ActionBinding::axis(
+                &[Key(KeyCode::Up)],
+                &[Key(KeyCode::Down)],
+                &[GamepadAxisType::RightStickY],
+            ),
+        );
+
+        Self {
+            bindings,
+            gamepad: None,
+        }
+    }
+}
+
+impl InputMap {
+    /// Bindings for an additional local player, using the right side of the
+    /// keyboard and the given gamepad.
+    pub fn local_player(gamepad: Gamepad) -> Self {
+        use InputBinding::*;
+
+        let mut bindings = HashMap::new();
+        bindings.insert(
+            Action::MoveForward,
+            ActionBinding::axis(
+                &[Key(KeyCode::I)],
+                &[Key(KeyCode::K)],
+                &[GamepadAxisType::LeftStickY],
+            ),
+        );
+        bindings.insert(
+            Action::MoveRight,
+            ActionBinding::axis(
+                &[Key(KeyCode::L)],
+                &[Key(KeyCode::J)],
+                &[GamepadAxisType::LeftStickX],
+            ),
+        );
+        bindings.insert(
+            Action::Jump,
+            ActionBinding::button(&[
+                Key(KeyCode::RShift),
+                GamepadButton(GamepadButtonType::South),
+            ]),
+        );
+        bindings.insert(
+            Action::Crouch,
+            ActionBinding::button(&[Key(KeyCode::M), GamepadButton(GamepadButtonType::East)]),
+        );
+        bindings.insert(
+            Action::Sprint,
+            ActionBinding::button(&[Key(KeyCode::N), GamepadButton(GamepadButtonType::LeftThumb)]),
+        );
+        bindings.insert(
+            Action::Dash,
+            ActionBinding::button(&[Key(KeyCode::U), GamepadButton(GamepadButtonType::West)]),
+        );
+        bindings.insert(
+            Action::CameraYaw,
+            ActionBinding::axis(
+                &[Key(KeyCode::Numpad6)],
+                &[Key(KeyCode::Numpad4)],
+                &[GamepadAxisType::RightStickX],
+            ),
+        );
+        bindings.insert(
+            Action::CameraPitch,
+            ActionBinding::axis(
+                &[Key(KeyCode::Numpad8)],
+                &[Key(KeyCode::Numpad2)],
+                &[GamepadAxisType::RightStickY],
+            ),
+        );
+
+        Self {
+            bindings,
+            gamepad: Some(gamepad),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct InputMapLoader;
+
+impl AssetLoader for InputMapLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let map: InputMap = ron::de::from_bytes(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(map));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["input.ron"]
+    }
+}
+
+/// Input map together with the raw input state it reads.
+#[derive(SystemParam)]
+pub struct ActionInput<'w, 's> {
+    pub map: Res<'w, InputMap>,
+    pub keys: Res<'w, Input<KeyCode>>,
+    pub mouse: Res<'w, Input<MouseButton>>,
+    pub gamepad_buttons: Res<'w, Input<GamepadButton>>,
+    pub gamepad_axes: Res<'w, Axis<GamepadAxis>>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
+impl<'w, 's> ActionInput<'w, 's> {
+    /// Reads actions through `map` if given, the global input map otherwise.
+    pub fn mapped<'a>(&'a self, map: Option<&'a LocalInputMap>) -> MappedInput<'a> {
+        MappedInput {
+            map: map.map_or(&*self.map, |m| &m.0),
+            keys: &self.keys,
+            mouse: &self.mouse,
+            gamepad_buttons: &self.gamepad_buttons,
+            gamepad_axes: &self.gamepad_axes,
+        }
+    }
+
+    pub fn pressed(&self, action: Action) -> bool {
+        self.mapped(None).pressed(action)
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.mapped(None).just_pressed(action)
+    }
+
+    pub fn just_released(&self, action: Action) -> bool {
+        self.mapped(None).just_released(action)
+    }
+
+    pub fn axis(&self, action: Action) -> f32 {
+        self.mapped(None).axis(action)
+    }
+
+    pub fn movement(&self, forward: Vec3, right: Vec3) -> Vec3 {
+        self.mapped(None).movement(forward, right)
+    }
+}
+
+/// Raw input state read through a specific input map.
+pub struct MappedInput<'a> {
+    map: &'a InputMap,
+    keys: &'a Input<KeyCode>,
+    mouse: &'a Input<MouseButton>,
+    gamepad_buttons: &'a Input<GamepadButton>,
+    gamepad_axes: &'a Axis<GamepadAxis>,
+}
+
+impl<'a> MappedInput<'a> {
+    /// Keys held together with Ctrl are editor shortcuts, like Ctrl+S saving
+    /// the level, and do not reach the actions.
+    fn ctrl_chord(&self, key: KeyCode) -> bool {
+        let ctrl = [KeyCode::LControl, KeyCode::RControl];
+        !ctrl.contains(&key) && ctrl.iter().any(|ctrl| self.keys.pressed(*ctrl))
+    }
+
+    fn check(&self, input: &InputBinding, f: impl Fn(ButtonState) -> bool) -> bool {
+        match *input {
+            InputBinding::Key(key) if self.ctrl_chord(key) => false,
+            InputBinding::Key(key) => f(ButtonState::of(self.keys, key)),
+            InputBinding::Mouse(button) => f(ButtonState::of(self.mouse, button)),
+            InputBinding::GamepadButton(button) => match self.map.gamepad {
+                Some(gamepad) => f(ButtonState::of(
+                    self.gamepad_buttons,
+                    GamepadButton(gamepad, button),
+                )),
+                None => false,
+            },
+        }
+    }
+
+    fn any(&self, inputs: &[InputBinding], f: impl Fn(ButtonState) -> bool + Copy) -> bool {
+        inputs.iter().any(|input| self.check(input, f))
+    }
+
+    pub fn pressed(&self, action: Action) -> bool {
+        match self.map.bindings.get(&action) {
+            Some(binding) => self.any(&binding.positive, |s| s.pressed),
+            None => false,
+        }
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        match self.map.bindings.get(&action) {
+            Some(binding) => self.any(&binding.positive, |s| s.just_pressed),
+            None => false,
+        }
+    }
+
+    pub fn just_released(&self, action: Action) -> bool {
+        match self.map.bindings.get(&action) {
+            Some(binding) => self.any(&binding.positive, |s| s.just_released),
+            None => false,
+        }
+    }
+
+    /// Value in [-1, 1] of an axis action.
+    pub fn axis(&self, action: Action) -> f32 {
+        let binding = match self.map.bindings.get(&action) {
+            Some(binding) => binding,
+            None => return 0.0,
+        };
+        let mut value = 0.0;
+        if self.any(&binding.positive, |s| s.pressed) {
+            value += 1.0;
+        }
+        if self.any(&binding.negative, |s| s.pressed) {
+            value -= 1.0;
+        }
+        if let Some(gamepad) = self.map.gamepad {
+            for axis_type in binding.axes.iter() {
+                value += self
+                    .gamepad_axes
+                    .get(GamepadAxis(gamepad, *axis_type))
+                    .unwrap_or(0.0);
+            }
+        }
+        value.clamp(-1.0, 1.0)
+    }
+
+    /// Planar movement in the camera space, keyboard diagonals are normalized
+    /// while analog input keeps its magnitude.
+    pub fn movement(&self, forward: Vec3, right: Vec3) -> Vec3 {
+        movement_direction(
+            forward,
+            right,
+            self.axis(Action::MoveForward),
+            self.axis(Action::MoveRight),
+        )
+    }
+}
+
+pub fn movement_direction(forward: Vec3, right: Vec3, forward_axis: f32, right_axis: f32) -> Vec3 {
+    (forward * forward_axis + right * right_axis).clamp_length_max(1.0)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ButtonState {
+    pressed: bool,
+    just_pressed: bool,
+    just_released: bool,
+}
+
+impl ButtonState {
+    fn of<T>(input: &Input<T>, button: T) -> Self
+    where
+        T: Copy + Eq + std::hash::Hash + Send + Sync + 'static,
+    {
+        Self {
+            pressed: input.pressed(button),
+            just_pressed: input.just_pressed(button),
+            just_released: input.just_released(button),
+        }
+    }
+}
+
+/// Input map used by an entity, e.g. a local player, instead of the global one.
+#[derive(Debug, Clone, Component)]
+pub struct LocalInputMap(pub InputMap);
+
+/// Input map asset loaded on startup, relative to `assets/`.
+#[derive(Debug)]
+pub struct InputMapSettings {
+    pub path: String,
+}
+
+impl Default for InputMapSettings {
+    fn default() -> Self {
+        Self {
+            path: "config/player.input.ron".into(),
+        }
+    }
+}
+
+/// Handle keeping the loaded input map asset alive.
+pub struct InputMapHandle(pub Handle<InputMap>);
+
+pub fn load_input_map(
+    mut commands: Commands,
+    settings: Res<InputMapSettings>,
+    asset_server: Res<AssetServer>,
+) {
+    let handle = asset_server.load(settings.path.as_str());
+    commands.insert_resource(InputMapHandle(handle));
+}
+
+/// Copies the input map asset into the `InputMap` resource when it is loaded
+/// and whenever the file changes on disk, so bindings can be edited while the
+/// game runs. The default map stays in use if the file fails to load.
+pub fn apply_input_map(
+    mut events: EventReader<AssetEvent<InputMap>>,
+    assets: Res<Assets<InputMap>>,
+    handle: Option<Res<InputMapHandle>>,
+    mut map: ResMut<InputMap>,
+) {
+    let handle = match handle {
+        Some(handle) => handle,
+        None => return,
+    };
+    for event in events.iter() {
+        match event {
+            AssetEvent::Created { handle: changed } | AssetEvent::Modified { handle: changed }
+                if *changed == handle.0 =>
+            {
+                if let Some(loaded) = assets.get(changed) {
+                    let gamepad = map.gamepad;
+                    *map = loaded.clone();
+                    map.gamepad = gamepad;
+                    info!("input map loaded");
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Gives the global map the first connected gamepad no local player owns.
+pub fn track_gamepad(
+    mut events: EventReader<GamepadEvent>,
+    mut map: ResMut<InputMap>,
+    local_maps: Query<&LocalInputMap>,
+) {
+    for GamepadEvent(gamepad, event_type) in events.iter() {
+        let owned = local_maps.iter().any(|m| m.0.gamepad == Some(*gamepad));
+        match event_type {
+            GamepadEventType::Connected if map.gamepad.is_none() && !owned => {
+                map.gamepad = Some(*gamepad);
+            }
+            GamepadEventType::Disconnected if map.gamepad == Some(*gamepad) => {
+                map.gamepad = None;
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,599 @@
+use std::cell::Cell;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use bevy_mod_picking::PickableBundle;
+use bevy_rapier3d::prelude::*;
+use serde::de::{DeserializeSeed, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::editor_enhanced::{TmpColliderType, TmpRigidBodyPosition, TmpRigidBodyType};
+use crate::health::{Checkpoint, Hazard};
+use crate::water::WaterVolume;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum LevelMesh {
+    Plane { size: f32 },
+    Box { x: f32, y: f32, z: f32 },
+    Sphere { radius: f32, subdivisions: usize },
+    Capsule { radius: f32, depth: f32 },
+}
+
+impl From<&LevelMesh> for Mesh {
+    fn from(m: &LevelMesh) -> Self {
+        use bevy::prelude::shape;
+        match *m {
+            LevelMesh::Plane { size } => shape::Plane { size }.into(),
+            LevelMesh::Box { x, y, z } => shape::Box::new(x, y, z).into(),
+            LevelMesh::Sphere {
+                radius,
+                subdivisions,
+            } => shape::Icosphere {
+                radius,
+                subdivisions,
+            }
+            .into(),
+            LevelMesh::Capsule { radius, depth } => shape::Capsule {
+                radius,
+                depth,
+                ..Default::default()
+            }
+            .into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LevelTransform {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Default for LevelTransform {
+    fn default() -> Self {
+        Self {
+            translation: Vec3::ZERO,
+            rotation: Quat::IDENTITY,
+            scale: Vec3::ONE,
+        }
+    }
+}
+
+impl From<&LevelTransform> for Transform {
+    fn from(t: &LevelTransform) -> Self {
+        Transform {
+            translation: t.translation,
+            rotation: t.rotation,
+            scale: t.scale,
+        }
+    }
+}
+
+impl From<&Transform> for LevelTransform {
+    fn from(t: &Transform) -> Self {
+        Self {
+            translation: t.translation,
+            rotation: t.rotation,
+            scale: t.scale,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LevelMaterial {
+    pub base_color: Color,
+    pub perceptual_roughness: f32,
+    pub metallic: f32,
+    pub reflectance: f32,
+    /// Blend with what is behind using the `base_color` alpha
+    pub alpha_blend: bool,
+    pub emissive: Color,
+    pub unlit: bool,
+    pub double_sided: bool,
+}
+
+impl Default for LevelMaterial {
+    fn default() -> Self {
+        Self::from_material(&StandardMaterial::default())
+            .expect("the default material has no textures")
+    }
+}
+
+impl From<&LevelMaterial> for StandardMaterial {
+    fn from(m: &LevelMaterial) -> Self {
+        StandardMaterial {
+            base_color: m.base_color,
+            perceptual_roughness: m.perceptual_roughness,
+            metallic: m.metallic,
+            reflectance: m.reflectance,
+            alpha_mode: if m.alpha_blend {
+                AlphaMode::Blend
+            } else {
+                AlphaMode::Opaque
+            },
+            emissive: m.emissive,
+            unlit: m.unlit,
+            double_sided: m.double_sided,
+            ..Default::default()
+        }
+    }
+}
+
+impl LevelMaterial {
+    /// `None` if the material uses textures or an alpha mask, which a level
+    /// can not describe.
+    pub fn from_material(m: &StandardMaterial) -> Option<Self> {
+        let textured = m.base_color_texture.is_some()
+            || m.emissive_texture.is_some()
+            || m.metallic_roughness_texture.is_some()
+            || m.normal_map_texture.is_some()
+            || m.occlusion_texture.is_some();
+        if textured || matches!(m.alpha_mode, AlphaMode::Mask(_)) {
+            return None;
+        }
+        Some(Self {
+            base_color: m.base_color,
+            perceptual_roughness: m.perceptual_roughness,
+            metallic: m.metallic,
+            reflectance: m.reflectance,
+            alpha_blend: matches!(m.alpha_mode, AlphaMode::Blend),
+            emissive: m.emissive,
+            unlit: m.unlit,
+            double_sided: m.double_sided,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum LevelColliderShape {
+    Cuboid { half_extents: Vec3 },
+    Ball { radius: f32 },
+    Capsule { half_height: f32, radius: f32 },
+}
+
+impl From<&LevelColliderShape> for ColliderShape {
+    fn from(s: &LevelColliderShape) -> Self {
+        match *s {
+            LevelColliderShape::Cuboid { half_extents } => {
+                ColliderShape::cuboid(half_extents.x, half_extents.y, half_extents.z)
+            }
+            LevelColliderShape::Ball { radius } => ColliderShape::ball(radius),
+            LevelColliderShape::Capsule {
+                half_height,
+                radius,
+            } => ColliderShape::capsule(
+                Vec3::new(0.0, half_height, 0.0).into(),
+                Vec3::new(0.0, -half_height, 0.0).into(),
+                radius,
+            ),
+        }
+    }
+}
+
+impl LevelColliderShape {
+    pub fn from_shape(shape: &ColliderShape) -> Option<Self> {
+        if let Some(cuboid) = shape.as_cuboid() {
+            Some(Self::Cuboid {
+                half_extents: cuboid.half_extents.into(),
+            })
+        } else if let Some(ball) = shape.as_ball() {
+            Some(Self::Ball {
+                radius: ball.radius,
+            })
+        } else {
+            shape.as_capsule().map(|capsule| Self::Capsule {
+                half_height: capsule.half_height(),
+                radius: capsule.radius,
+            })
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LevelCollider {
+    pub shape: LevelColliderShape,
+    #[serde(default)]
+    pub sensor: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LevelRigidBodyType {
+    Dynamic,
+    Static,
+    KinematicPositionBased,
+    KinematicVelocityBased,
+}
+
+impl From<RigidBodyType> for LevelRigidBodyType {
+    fn from(t: RigidBodyType) -> Self {
+        match t {
+            RigidBodyType::Dynamic => Self::Dynamic,
+            RigidBodyType::Static => Self::Static,
+            RigidBodyType::KinematicPositionBased => Self::KinematicPositionBased,
+            RigidBodyType::KinematicVelocityBased => Self::KinematicVelocityBased,
+        }
+    }
+}
+
+impl From<LevelRigidBodyType> for TmpRigidBodyType {
+    fn from(t: LevelRigidBodyType) -> Self {
+        Self {
+            dynamic: t == LevelRigidBodyType::Dynamic,
+            static_: t == LevelRigidBodyType::Static,
+            kinematic_position_based: t == LevelRigidBodyType::KinematicPositionBased,
+            kinematic_velocity_based: t == LevelRigidBodyType::KinematicVelocityBased,
+        }
+    }
+}
+
+impl From<LevelRigidBodyType> for RigidBodyType {
+    fn from(t: LevelRigidBodyType) -> Self {
+        match t {
+            LevelRigidBodyType::Dynamic => Self::Dynamic,
+            LevelRigidBodyType::Static => Self::Static,
+            LevelRigidBodyType::KinematicPositionBased => Self::KinematicPositionBased,
+            LevelRigidBodyType::KinematicVelocityBased => Self::KinematicVelocityBased,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LevelRigidBody {
+    pub body_type: LevelRigidBodyType,
+    /// Initial body position, defaults to the entity translation.
+    #[serde(default)]
+    pub position: Option<Vec3>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LevelEntity {
+    pub name: String,
+    pub mesh: LevelMesh,
+    #[serde(default)]
+    pub transform: LevelTransform,
+    #[serde(default)]
+    pub material: LevelMaterial,
+    #[serde(default)]
+    pub collider: Option<LevelCollider>,
+    #[serde(default)]
+    pub rigid_body: Option<LevelRigidBody>,
+    /// Makes the entity a water volume, needs a sensor collider
+    #[serde(default)]
+    pub water: Option<WaterVolume>,
+    /// Makes the entity damage the player, needs a solid collider
+    #[serde(default)]
+    pub hazard: Option<Hazard>,
+    /// Makes the entity a checkpoint, needs a sensor collider
+    #[serde(default)]
+    pub checkpoint: bool,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+#[serde(transparent)]
+pub struct Level {
+    pub entities: Vec<LevelEntity>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LevelFormat {
+    Ron,
+    Json,
+}
+
+impl LevelFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "ron" => Some(Self::Ron),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LevelError {
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    UnknownFormat {
+        path: PathBuf,
+    },
+    /// `entry` is the index of the entity being parsed when the error occurred,
+    /// `None` if the file is malformed before any entry.
+    Parse {
+        path: PathBuf,
+        entry: Option<usize>,
+        message: String,
+    },
+    InvalidEntry {
+        path: PathBuf,
+        entry: usize,
+        name: String,
+        reason: String,
+    },
+    Serialize {
+        path: PathBuf,
+        message: String,
+    },
+}
+
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            Self::UnknownFormat { path } => {
+                write!(f, "{}: expected a .ron or .json level file", path.display())
+            }
+            Self::Parse {
+                path,
+                entry: Some(entry),
+                message,
+            } => write!(f, "{}: entry {}: {}", path.display(), entry, message),
+            Self::Parse {
+                path,
+                entry: None,
+                message,
+            } => write!(f, "{}: {}", path.display(), message),
+            Self::InvalidEntry {
+                path,
+                entry,
+                name,
+                reason,
+            } => write!(
+                f,
+                "{}: entry {} ({:?}): {}",
+                path.display(),
+                entry,
+                name,
+                reason
+            ),
+            Self::Serialize { path, message } => write!(f, "{}: {}", path.display(), message),
+        }
+    }
+}
+
+impl std::error::Error for LevelError {}
+
+/// Deserializes the list of level entities while keeping track of
+/// which entry is being parsed, so errors can point at it.
+struct EntriesSeed<'a> {
+    current: &'a Cell<Option<usize>>,
+}
+
+impl<'de, 'a> DeserializeSeed<'de> for EntriesSeed<'a> {
+    type Value = Vec<LevelEntity>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, 'a> Visitor<'de> for EntriesSeed<'a> {
+    type Value = Vec<LevelEntity>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a list of level entities")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut entities = Vec::new();
+        loop {
+            self.current.set(Some(entities.len()));
+            match seq.next_element::<LevelEntity>()? {
+                Some(entity) => entities.push(entity),
+                None => break,
+            }
+        }
+        self.current.set(None);
+        Ok(entities)
+    }
+}
+
+impl Level {
+    pub fn parse(path: &Path, format: LevelFormat, source: &str) -> Result<Self, LevelError> {
+        let current = Cell::new(None);
+        let seed = EntriesSeed { current: &current };
+        let parse_error = |message: String| LevelError::Parse {
+            path: path.to_path_buf(),
+            entry: current.get(),
+            message,
+        };
+
+        let entities = match format {
+            LevelFormat::Ron => {
+                let mut de =
+                    ron::Deserializer::from_str(source).map_err(|e| parse_error(e.to_string()))?;
+                let entities = seed
+                    .deserialize(&mut de)
+                    .map_err(|e| parse_error(e.to_string()))?;
+                de.end().map_err(|e| parse_error(e.to_string()))?;
+                entities
+            }
+            LevelFormat::Json => {
+                let mut de = serde_json::Deserializer::from_str(source);
+                let entities = seed
+                    .deserialize(&mut de)
+                    .map_err(|e| parse_error(e.to_string()))?;
+                de.end().map_err(|e| parse_error(e.to_string()))?;
+                entities
+            }
+        };
+
+        let level = Self { entities };
+        level.validate(path)?;
+        Ok(level)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, LevelError> {
+        let path = path.as_ref();
+        let format = LevelFormat::from_path(path).ok_or_else(|| LevelError::UnknownFormat {
+            path: path.to_path_buf(),
+        })?;
+        let source = std::fs::read_to_string(path).map_err(|error| LevelError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        Self::parse(path, format, &source)
+    }
+
+    /// Writes the level, refusing levels that `load` would reject.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), LevelError> {
+        let path = path.as_ref();
+        self.validate(path)?;
+        let format = LevelFormat::from_path(path).ok_or_else(|| LevelError::UnknownFormat {
+            path: path.to_path_buf(),
+        })?;
+        let serialize_error = |message: String| LevelError::Serialize {
+            path: path.to_path_buf(),
+            message,
+        };
+
+        let source = match format {
+            LevelFormat::Ron => ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::new())
+                .map_err(|e| serialize_error(e.to_string()))?,
+            LevelFormat::Json => {
+                serde_json::to_string_pretty(self).map_err(|e| serialize_error(e.to_string()))?
+            }
+        };
+        std::fs::write(path, source).map_err(|error| LevelError::Io {
+            path: path.to_path_buf(),
+            error,
+        })
+    }
+
+    fn validate(&self, path: &Path) -> Result<(), LevelError> {
+        for (i, entity) in self.entities.iter().enumerate() {
+            let invalid = |reason: &str| LevelError::InvalidEntry {
+                path: path.to_path_buf(),
+                entry: i,
+                name: entity.name.clone(),
+                reason: reason.into(),
+            };
+
+            let positive = match entity.mesh {
+                LevelMesh::Plane { size } => size > 0.0,
+                LevelMesh::Box { x, y, z } => x > 0.0 && y > 0.0 && z > 0.0,
+                LevelMesh::Sphere { radius, .. } => radius > 0.0,
+                LevelMesh::Capsule { radius, depth } => radius > 0.0 && depth >= 0.0,
+            };
+            if !positive {
+                return Err(invalid("mesh dimensions must be positive"));
+            }
+
+            if let Some(collider) = &entity.collider {
+                let positive = match collider.shape {
+                    LevelColliderShape::Cuboid { half_extents } => half_extents.min_element() > 0.0,
+                    LevelColliderShape::Ball { radius } => radius > 0.0,
+                    LevelColliderShape::Capsule {
+                        half_height,
+                        radius,
+                    } => half_height >= 0.0 && radius > 0.0,
+                };
+                if !positive {
+                    return Err(invalid("collider dimensions must be positive"));
+                }
+            }
+
+            if entity.rigid_body.is_some() && entity.collider.is_none() {
+                return Err(invalid("rigid body without a collider"));
+            }
+
+            let sensor = entity.collider.as_ref().map(|c| c.sensor);
+            if entity.water.is_some() && sensor != Some(true) {
+                return Err(invalid("water without a sensor collider"));
+            }
+            if entity.checkpoint && sensor != Some(true) {
+                return Err(invalid("checkpoint without a sensor collider"));
+            }
+            if entity.hazard.is_some() && sensor != Some(false) {
+                return Err(invalid("hazard without a solid collider"));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn spawn(
+        &self,
+        commands: &mut Commands,
+        meshes: &mut Assets<Mesh>,
+        materials: &mut Assets<StandardMaterial>,
+    ) {
+        for entity in self.entities.iter() {
+            spawn_entity(commands, meshes, materials, entity);
+        }
+    }
+}
+
+/// Mesh description an entity was spawned from, kept so the level can be saved again.
+#[derive(Debug, Component)]
+pub struct LevelMeshSource(pub LevelMesh);
+
+pub fn spawn_entity(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    entity: &LevelEntity,
+) -> Entity {
+    let transform: Transform = (&entity.transform).into();
+    let mut e = commands.spawn_bundle(PbrBundle {
+        mesh: meshes.add((&entity.mesh).into()),
+        material: materials.add((&entity.material).into()),
+        transform,
+        ..Default::default()
+    });
+    e.insert(Name::new(entity.name.clone()))
+        .insert(LevelMeshSource(entity.mesh.clone()))
+        .insert_bundle(PickableBundle::default());
+
+    if let Some(rigid_body) = &entity.rigid_body {
+        let position = rigid_body.position.unwrap_or(transform.translation);
+        e.insert_bundle(RigidBodyBundle {
+            body_type: RigidBodyTypeComponent(rigid_body.body_type.into()),
+            position: (position, transform.rotation).into(),
+            ..Default::default()
+        })
+        .insert(RigidBodyPositionSync::Discrete)
+        .insert(TmpRigidBodyType::from(rigid_body.body_type))
+        .insert(TmpRigidBodyPosition(position));
+    }
+
+    if let Some(collider) = &entity.collider {
+        let collider_type = if collider.sensor {
+            ColliderType::Sensor
+        } else {
+            ColliderType::Solid
+        };
+        // colliders attached to a rigid body are positioned relative to it
+        let position = match entity.rigid_body {
+            Some(_) => Vec3::ZERO.into(),
+            None => (transform.translation, transform.rotation).into(),
+        };
+        e.insert_bundle(ColliderBundle {
+            collider_type: ColliderTypeComponent(collider_type),
+            shape: ColliderShape::from(&collider.shape).into(),
+            position,
+            ..Default::default()
+        })
+        .insert(TmpColliderType {
+            solid: !collider.sensor,
+            sensor: collider.sensor,
+        });
+    }
+
+    if let Some(water) = &entity.water {
+        e.insert(water.clone());
+    }
+    if let Some(hazard) = &entity.hazard {
+        e.insert(hazard.clone());
+    }
+    if entity.checkpoint {
+        e.insert(Checkpoint);
+    }
+
+    e.id()
+}
@@ -1,3 +1,4 @@
+use bevy::asset::AssetServerSettings;
 use bevy::diagnostic::*;
 use bevy::prelude::*;
 use bevy_editor_pls::prelude::*;
@@ -7,13 +8,30 @@ use bevy_rapier3d::prelude::*;
 mod animated_shader;
 mod debug_line;
 mod editor_enhanced;
+mod fixed_step;
 mod follower;
-mod scene;
+mod ground_probe;
+mod health;
+mod input_map;
+mod level;
+mod platform;
 mod player;
+mod preset;
+mod replay;
+mod scene;
+mod stance;
+#[cfg(test)]
+mod test_harness;
+mod water;
 
 fn main() {
     App::new()
         // default
+        // watch assets so controller presets reload while the game runs
+        .insert_resource(AssetServerSettings {
+            watch_for_changes: true,
+            ..Default::default()
+        })
         .add_plugins(DefaultPlugins)
         .add_plugin(FrameTimeDiagnosticsPlugin)
         .add_plugin(EntityCountDiagnosticsPlugin)
@@ -23,14 +41,20 @@ fn main() {
         // .add_system_to_stage(CoreStage::PostUpdate, print_events)
         // physics
         .add_plugin(RapierPhysicsPlugin::<NoUserData>::default())
+        .add_plugin(fixed_step::FixedStepPlugin)
         // custom
+        .add_plugin(input_map::InputMapPlugin)
+        .insert_resource(replay::ReplaySettings::from_args(std::env::args().skip(1)))
+        .add_plugin(replay::ReplayPlugin)
         .add_plugin(animated_shader::CustomMaterialPlugin)
         .add_plugin(follower::FollowCameraPlugin)
         .add_plugin(editor_enhanced::EditorAdditionsPlugin)
+        .insert_resource(player::LocalPlayers::from_args(std::env::args().skip(1)))
         .add_plugin(player::PlayerPlugin)
+        .add_plugin(preset::PresetPlugin)
+        .add_plugin(health::HealthPlugin)
         .add_plugin(scene::ScenePlugin)
         .add_system(bevy::input::system::exit_on_esc_system)
-        .add_system(display_events)
         .run();
 }
 
@@ -43,16 +67,3 @@ fn main() {
 //         }
 //     }
 // }
-
-fn display_events(
-    mut intersection_events: EventReader<IntersectionEvent>,
-    mut contact_events: EventReader<ContactEvent>,
-) {
-    for intersection_event in intersection_events.iter() {
-        println!("Received intersection event: {:?}", intersection_event);
-    }
-
-    for contact_event in contact_events.iter() {
-        println!("Received contact event: {:?}", contact_event);
-    }
-}
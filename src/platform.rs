@@ -0,0 +1,134 @@
+use bevy::prelude::*;
+use bevy_mod_picking::PickableBundle;
+use bevy_rapier3d::physics::{PhysicsStages, PhysicsSystems};
+use bevy_rapier3d::prelude::*;
+
+use crate::fixed_step::FixedStep;
+
+pub struct PlatformPlugin;
+
+impl Plugin for PlatformPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Elevator>()
+            .register_type::<SurfaceVelocity>()
+            .add_system_to_stage(
+                PhysicsStages::StepWorld,
+                move_elevators.before(PhysicsSystems::StepWorld),
+            );
+    }
+}
+
+/// Kinematic position based body moving back and forth along `offset`.
+#[derive(Debug, Default, Component, Reflect)]
+#[reflect(Component)]
+pub struct Elevator {
+    pub origin: Vec3,
+    pub offset: Vec3,
+    /// Seconds for a full round trip
+    pub period: f32,
+}
+
+/// Velocity given to whatever stands on the surface without the body moving,
+/// e.g. a conveyor belt.
+#[derive(Debug, Default, Component, Reflect)]
+#[reflect(Component)]
+pub struct SurfaceVelocity(pub Vec3);
+
+/// Runs every fixed step, a kinematic target set once per frame would stop
+/// the elevator on frames with several steps.
+pub fn move_elevators(
+    step: Res<FixedStep>,
+    mut elevators: Query<(&Elevator, &mut RigidBodyPositionComponent)>,
+) {
+    let t = step.elapsed as f32;
+    for (elevator, mut rb_pos) in elevators.iter_mut() {
+        let phase = (t / elevator.period * std::f32::consts::TAU).sin() * 0.5 + 0.5;
+        let translation = elevator.origin + elevator.offset * phase;
+        rb_pos.next_position.translation.vector = translation.into();
+    }
+}
+
+/// Velocity of the ground body at `point`, including its rotation.
+pub fn point_velocity(
+    rb_vel: &RigidBodyVelocity,
+    rb_mprops: &RigidBodyMassProps,
+    surface: Option<&SurfaceVelocity>,
+    point: Vec3,
+) -> Vec3 {
+    let linvel: Vec3 = rb_vel.linvel.into();
+    let angvel: Vec3 = rb_vel.angvel.into();
+    let com: Vec3 = rb_mprops.world_com.coords.into();
+    let surface = surface.map_or(Vec3::ZERO, |s| s.0);
+    linvel + angvel.cross(point - com) + surface
+}
+
+pub fn setup_platforms(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let material = materials.add(StandardMaterial {
+        base_color: Color::rgb(0.4, 0.4, 0.8),
+        perceptual_roughness: 0.8,
+        ..Default::default()
+    });
+    let mut platform = |name: &str, position: Vec3, half_extents: Vec3, body_type| {
+        let mut e = commands.spawn_bundle(PbrBundle {
+            mesh: meshes.add(Mesh::from(bevy::prelude::shape::Box::new(
+                half_extents.x * 2.0,
+                half_extents.y * 2.0,
+                half_extents.z * 2.0,
+            ))),
+            material: material.clone(),
+            ..Default::default()
+        });
+        e.insert_bundle(RigidBodyBundle {
+            body_type: RigidBodyTypeComponent(body_type),
+            position: position.into(),
+            ..Default::default()
+        })
+        .insert_bundle(ColliderBundle {
+            shape: ColliderShape::cuboid(half_extents.x, half_extents.y, half_extents.z).into(),
+            ..Default::default()
+        })
+        .insert(RigidBodyPositionSync::Discrete)
+        .insert_bundle(PickableBundle::default())
+        .insert(Name::new(name.to_string()));
+        e.id()
+    };
+
+    let origin = Vec3::new(-12.0, 0.25, 12.0);
+    let elevator = platform(
+        "Elevator",
+        origin,
+        Vec3::new(2.0, 0.25, 2.0),
+        RigidBodyType::KinematicPositionBased,
+    );
+    let conveyor = platform(
+        "Conveyor",
+        Vec3::new(0.0, 0.25, 15.0),
+        Vec3::new(6.0, 0.25, 1.5),
+        RigidBodyType::Static,
+    );
+    let rotating = platform(
+        "Rotating platform",
+        Vec3::new(12.0, 0.25, 12.0),
+        Vec3::new(4.0, 0.25, 4.0),
+        RigidBodyType::KinematicVelocityBased,
+    );
+
+    commands.entity(elevator).insert(Elevator {
+        origin,
+        offset: Vec3::new(0.0, 6.0, 0.0),
+        period: 8.0,
+    });
+    commands
+        .entity(conveyor)
+        .insert(SurfaceVelocity(Vec3::new(3.0, 0.0, 0.0)));
+    commands
+        .entity(rotating)
+        .insert(RigidBodyVelocityComponent(RigidBodyVelocity {
+            linvel: Vector::zeros(),
+            angvel: Vec3::new(0.0, 0.5, 0.0).into(),
+        }));
+}
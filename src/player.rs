@@ -1,58 +1,588 @@
 use std::ops::Deref;
+
 use bevy::prelude::*;
 use bevy_mod_picking::{PickableBundle, PickingCameraBundle};
-use bevy_mod_raycast::{DefaultPluginState, DefaultRaycastingPlugin, RayCastSource};
+use bevy_rapier3d::physics::{PhysicsStages, PhysicsSystems};
 use bevy_rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
 
+use crate::fixed_step::{FixedStep, InterpolatedTransform};
 use crate::follower;
+use crate::ground_probe::{
+    self, CeilingProbe, GroundHit, GroundProbe, LedgeProbe, WallHit, WallProbe,
+};
+use crate::health::{Health, RespawnPoint};
+use crate::input_map::{Action, ActionInput, InputMap, LocalInputMap};
+use crate::platform::{self, SurfaceVelocity};
+use crate::stance::{self, MovementProfile, PlayerStance};
+use crate::water::{InWater, WaterVolume};
 
 pub struct PlayerPlugin;
 
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugin(DefaultRaycastingPlugin::<Player>::default())
-            .insert_resource(DefaultPluginState::<Player>::default())
+        app.init_resource::<ControllerTime>()
+            .init_resource::<LocalPlayers>()
             .register_type::<PlayerControllerSettings>()
-            .add_startup_system(setup_camera)
+            .register_type::<MovementProfile>()
+            .add_event::<PlayerMovementStateChanged>()
+            .add_event::<DashStarted>()
+            .add_event::<DashEnded>()
             .add_startup_system(setup_player)
-            .add_system(apply_forces);
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                update_controller_time.label(PlayerSystem::ControllerTime),
+            )
+            .add_system(latch_player_input)
+            // forces are reset by every physics step, so they are applied right before each one
+            .add_system_set_to_stage(
+                PhysicsStages::StepWorld,
+                SystemSet::new()
+                    .before(PhysicsSystems::StepWorld)
+                    .with_system(ground_probe::update_probes.label(PlayerSystem::GroundProbe))
+                    .with_system(
+                        read_player_intent
+                            .label(PlayerSystem::Intent)
+                            .after(PlayerSystem::GroundProbe),
+                    )
+                    .with_system(
+                        stance::update_stance
+                            .label(PlayerSystem::Stance)
+                            .after(PlayerSystem::Intent),
+                    )
+                    .with_system(swim.label(PlayerSystem::Swim).after(PlayerSystem::Stance))
+                    .with_system(
+                        grab_ledges
+                            .label(PlayerSystem::Ledge)
+                            .after(PlayerSystem::Swim),
+                    )
+                    .with_system(
+                        wall_jump
+                            .label(PlayerSystem::Wall)
+                            .after(PlayerSystem::Ledge),
+                    )
+                    .with_system(
+                        update_movement_state
+                            .label(PlayerSystem::MovementState)
+                            .after(PlayerSystem::Wall),
+                    )
+                    .with_system(
+                        dash.label(PlayerSystem::Dash)
+                            .after(PlayerSystem::MovementState),
+                    )
+                    .with_system(
+                        apply_forces
+                            .label(PlayerSystem::ApplyForces)
+                            .after(PlayerSystem::Dash),
+                    )
+                    .with_system(stance::update_stance_collider.after(PlayerSystem::ApplyForces)),
+            );
     }
 }
 
+/// Player systems, the fixed step ones run in the order listed.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemLabel)]
+pub enum PlayerSystem {
+    ControllerTime,
+    GroundProbe,
+    Intent,
+    Stance,
+    Swim,
+    Ledge,
+    Wall,
+    MovementState,
+    Dash,
+    ApplyForces,
+}
+
 #[derive(Component)]
 pub struct Player;
 
-#[derive(Debug, Default, Component, Reflect)]
+/// Frame clock driving the fixed physics step and the cameras. It follows
+/// `Time` unless `manual` is set, in which case whoever set it advances it,
+/// e.g. the test harness.
+#[derive(Debug, Default)]
+pub struct ControllerTime {
+    pub delta: f32,
+    pub elapsed: f64,
+    pub manual: bool,
+}
+
+pub fn update_controller_time(time: Res<Time>, mut clock: ResMut<ControllerTime>) {
+    if !clock.manual {
+        clock.delta = time.delta_seconds();
+        clock.elapsed = time.seconds_since_startup();
+    }
+}
+
+/// Number of players spawned on startup. The first one reads the global
+/// input map, the others get their own keys and gamepad.
+#[derive(Debug)]
+pub struct LocalPlayers {
+    pub count: usize,
+}
+
+impl Default for LocalPlayers {
+    fn default() -> Self {
+        Self { count: 1 }
+    }
+}
+
+impl LocalPlayers {
+    /// Reads `--players <count>` from the command line.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Self {
+        let mut players = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "--players" {
+                if let Some(count) = args.next().and_then(|count| count.parse().ok()) {
+                    players.count = count;
+                }
+            }
+        }
+        players
+    }
+}
+
+/// Follower camera the player moves relative to.
+#[derive(Component)]
+pub struct PlayerCamera(pub Entity);
+
+#[derive(Debug, Default, Clone, Component, Reflect, Serialize, Deserialize)]
 #[reflect(Component)]
 pub struct PlayerControllerSettings {
+    /// Top planar speed
     pub max_speed: f32,
+    /// Rate at which the goal velocity approaches the input velocity
     pub acceleration: f32,
+    /// Cap on the acceleration used to reach the goal velocity
     pub max_accel_force: f32,
     pub ride_height: f32,
-    pub force_str: f32,
+    /// Distance past `ride_height` at which the player still counts as grounded
+    pub ground_tolerance: f32,
+    /// Steepest walkable slope in degrees, the player slides down steeper ones
+    pub max_slope_angle: f32,
+    /// Push the hover spring along the ground normal instead of straight up
+    pub spring_along_normal: bool,
+    /// Apply the opposite spring force to dynamic bodies under the player
+    pub push_ground_bodies: bool,
     pub spring_str: f32,
     pub spring_damper: f32,
     pub upright_spring_str: f32,
     pub upright_spring_damper: f32,
     pub rotate_str: f32,
     pub jump_str: f32,
+    /// Gravity acceleration applied by the controller while airborne
+    pub gravity: f32,
+    /// Gravity multiplier while moving down
+    pub fall_gravity_multiplier: f32,
+    /// Vertical velocity multiplier when jump is released while rising
+    pub jump_cut_multiplier: f32,
+    /// Seconds after leaving the ground during which a jump is still allowed
+    pub coyote_time: f32,
+    /// Seconds a jump press is remembered before landing
+    pub jump_buffer_time: f32,
+    pub capsule_half_height: f32,
+    pub capsule_radius: f32,
+    pub crouch: MovementProfile,
+    pub sprint: MovementProfile,
+    /// Rate at which the stance blends between profiles, 1 / seconds
+    pub stance_blend_speed: f32,
+    pub dash_str: f32,
+    /// Seconds the hover spring and steering are suspended after a dash
+    pub dash_duration: f32,
+    /// Minimum seconds between two dashes
+    pub dash_cooldown: f32,
+    /// Dashes available before touching the ground again
+    pub dash_charges: u32,
+    /// Distance from the player center at which a wall is touched
+    pub wall_distance: f32,
+    /// Top fall speed while sliding down a wall
+    pub wall_slide_speed: f32,
+    /// Impulse away from the wall on a wall jump, added to `jump_str` upwards
+    pub wall_jump_str: f32,
+    /// Height above the player center of the ledge chest ray, and of the hands while hanging
+    pub ledge_chest_height: f32,
+    /// Height above the player center the ledge top ray starts from
+    pub ledge_top_height: f32,
+    /// Distance in front of the player of the ledge top ray
+    pub ledge_reach: f32,
+    /// Furthest wall distance at which a ledge can be grabbed
+    pub ledge_grab_distance: f32,
+    /// Gain of the velocity pulling the player to the hang and climb positions
+    pub ledge_snap_str: f32,
+    /// Top speed while climbing onto a ledge
+    pub ledge_climb_speed: f32,
+    /// Top speed in any direction while swimming
+    pub swim_speed: f32,
+    pub swim_acceleration: f32,
 }
 
-pub fn setup_camera(mut commands: Commands) {
-    commands.insert_resource(follower::FollowerController {
-        follower_id: 0,
-        rotation_speed: 3.0,
-        ..Default::default()
-    });
+impl PlayerControllerSettings {
+    /// Values of the standard preset file, built in so they are there before
+    /// the presets load and for the test harness.
+    pub fn standard() -> Self {
+        ron::from_str(include_str!("../assets/presets/standard.preset.ron"))
+            .expect("standard preset is valid")
+    }
 
+    /// Standing profile made of the top level values.
+    pub fn base_profile(&self) -> MovementProfile {
+        MovementProfile {
+            ride_height: self.ride_height,
+            capsule_half_height: self.capsule_half_height,
+            max_speed: self.max_speed,
+            acceleration: self.acceleration,
+        }
+    }
+
+    /// Ground hit close enough to stand on.
+    pub fn ground(&self, probe: &GroundProbe) -> Option<GroundHit> {
+        probe
+            .hit
+            .filter(|hit| hit.distance <= self.ride_height + self.ground_tolerance)
+    }
+
+    /// Whether a surface is steeper than `max_slope_angle`.
+    pub fn is_steep(&self, normal: Vec3) -> bool {
+        normal.angle_between(Vec3::Y) > self.max_slope_angle.to_radians()
+    }
+
+    pub fn capsule(&self, half_height: f32) -> ColliderShape {
+        ColliderShape::capsule(
+            Vec3::new(0.0, half_height, 0.0).into(),
+            Vec3::new(0.0, -half_height, 0.0).into(),
+            self.capsule_radius,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component)]
+pub enum PlayerMovementState {
+    Grounded,
+    /// Off the ground without moving down, e.g. just after walking off a ledge
+    Airborne,
+    /// Moving up after a jump
+    Jumping,
+    Falling,
+    /// First frame back on the ground
+    Landing,
+    /// Falling while pressed against a wall
+    WallSliding,
+    /// Holding onto a ledge
+    Hanging,
+    /// Climbing from a ledge onto its top
+    Mantling,
+    /// Inside a water volume
+    Swimming,
+}
+
+impl Default for PlayerMovementState {
+    fn default() -> Self {
+        Self::Airborne
+    }
+}
+
+impl PlayerMovementState {
+    pub fn is_grounded(&self) -> bool {
+        matches!(self, Self::Grounded | Self::Landing)
+    }
+
+    pub fn next(self, grounded: bool, on_wall: bool, vertical_velocity: f32, jumped: bool) -> Self {
+        if jumped {
+            return Self::Jumping;
+        }
+        match (self, grounded) {
+            // keep rising through the ground tolerance after a jump
+            (Self::Jumping, _) if vertical_velocity > 0.0 => Self::Jumping,
+            (Self::Grounded | Self::Landing, true) => Self::Grounded,
+            (_, true) => Self::Landing,
+            (_, false) if on_wall && vertical_velocity < 0.0 => Self::WallSliding,
+            (_, false) if vertical_velocity < 0.0 => Self::Falling,
+            (_, false) => Self::Airborne,
+        }
+    }
+
+    /// Switches to `state`, sending a change event if it differs.
+    pub fn set(
+        &mut self,
+        state: Self,
+        player: Entity,
+        events: &mut EventWriter<PlayerMovementStateChanged>,
+    ) {
+        if state != *self {
+            events.send(PlayerMovementStateChanged {
+                player,
+                previous: *self,
+                current: state,
+            });
+            *self = state;
+        }
+    }
+}
+
+// The controller only sends these events, animation, sound and UI code
+// listens to them, so their fields are not read in this crate.
+
+#[allow(dead_code)]
+pub struct PlayerMovementStateChanged {
+    pub player: Entity,
+    pub previous: PlayerMovementState,
+    pub current: PlayerMovementState,
+}
+
+#[allow(dead_code)]
+pub struct DashStarted {
+    pub player: Entity,
+    /// Planar direction of the dash impulse
+    pub direction: Vec3,
+}
+
+#[allow(dead_code)]
+pub struct DashEnded {
+    pub player: Entity,
+}
+
+/// What the player asks for this fixed step, read from the input and the camera.
+#[derive(Debug, Default, Component)]
+pub struct PlayerIntent {
+    /// Planar movement relative to the camera
+    pub movement: Vec3,
+    /// Movement along the camera view while swimming, jump and crouch move up and down
+    pub swim_movement: Vec3,
+    /// Planar direction the camera looks along, the player turns towards it
+    pub forward: Vec3,
+    pub jump: bool,
+    pub jump_released: bool,
+    pub dash: bool,
+    pub crouch: bool,
+    pub sprint: bool,
+}
+
+/// Presses seen since the last fixed step, so none are lost on frames
+/// without a step or repeated on frames with several.
+#[derive(Debug, Default, Component)]
+pub struct PlayerInputLatch {
+    pub jump: bool,
+    pub jump_released: bool,
+    pub dash: bool,
+}
+
+pub fn latch_player_input(
+    input: ActionInput,
+    mut players: Query<(&mut PlayerInputLatch, Option<&LocalInputMap>)>,
+) {
+    for (mut latch, map) in players.iter_mut() {
+        let input = input.mapped(map);
+        latch.jump |= input.just_pressed(Action::Jump);
+        latch.jump_released |= input.just_released(Action::Jump);
+        latch.dash |= input.just_pressed(Action::Dash);
+    }
+}
+
+#[derive(Debug, Default, Component)]
+pub struct PlayerDash {
+    pub charges: u32,
+    pub started_at: Option<f64>,
+    pub last_dash_at: Option<f64>,
+}
+
+impl PlayerDash {
+    pub fn is_dashing(&self) -> bool {
+        self.started_at.is_some()
+    }
+
+    /// Refills the charges on walkable ground and returns true on the frame
+    /// the current dash ends.
+    pub fn update(
+        &mut self,
+        settings: &PlayerControllerSettings,
+        grounded: bool,
+        now: f64,
+    ) -> bool {
+        if grounded && !self.is_dashing() {
+            self.charges = settings.dash_charges;
+        }
+        match self.started_at {
+            Some(t) if now - t >= settings.dash_duration as f64 => {
+                self.started_at = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Starts a dash if a charge is available and the cooldown has passed.
+    pub fn try_dash(&mut self, settings: &PlayerControllerSettings, now: f64) -> bool {
+        let cooled_down = self
+            .last_dash_at
+            .map_or(true, |t| now - t >= settings.dash_cooldown as f64);
+        if self.charges > 0 && cooled_down && !self.is_dashing() {
+            self.charges -= 1;
+            self.started_at = Some(now);
+            self.last_dash_at = Some(now);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Ledge {
+    /// Player center while hanging
+    pub hang_position: Vec3,
+    /// Player center standing on top of the ledge
+    pub climb_position: Vec3,
+}
+
+impl Ledge {
+    /// Builds a ledge from the ledge probe hits if the wall is close enough and
+    /// the top is walkable and above the chest.
+    pub fn detect(
+        settings: &PlayerControllerSettings,
+        position: Vec3,
+        forward: Vec3,
+        wall: &WallHit,
+        top: &GroundHit,
+    ) -> Option<Self> {
+        let normal = Vec3::new(wall.normal.x, 0.0, wall.normal.z).try_normalize()?;
+        let walkable = top.normal.angle_between(Vec3::Y) <= settings.max_slope_angle.to_radians();
+        let height = settings.ledge_top_height - top.distance;
+        if wall.distance > settings.ledge_grab_distance
+            || !walkable
+            || height < settings.ledge_chest_height
+        {
+            return None;
+        }
+
+        let contact = position + forward * wall.distance;
+        let mut hang_position = contact + normal * settings.capsule_radius;
+        hang_position.y = top.point.y - settings.ledge_chest_height;
+        let climb_position =
+            top.point - normal * settings.capsule_radius + Vec3::Y * settings.ride_height;
+        Some(Self {
+            hang_position,
+            climb_position,
+        })
+    }
+}
+
+#[derive(Debug, Default, Component)]
+pub struct PlayerMovement {
+    /// Planar velocity the controller is currently steering towards
+    pub goal_velocity: Vec3,
+    pub last_grounded_at: Option<f64>,
+    pub jump_requested_at: Option<f64>,
+    /// Ledge held or climbed onto
+    pub ledge: Option<Ledge>,
+    /// Wall pressed against in the air
+    pub wall: Option<WallHit>,
+}
+
+impl PlayerMovement {
+    /// Whether the player was grounded recently enough to jump.
+    pub fn coyote(
+        &self,
+        settings: &PlayerControllerSettings,
+        state: PlayerMovementState,
+        now: f64,
+    ) -> bool {
+        state != PlayerMovementState::Jumping
+            && self
+                .last_grounded_at
+                .map_or(false, |t| now - t <= settings.coyote_time as f64)
+    }
+
+    /// Consumes a buffered jump request if the player was grounded recently enough.
+    pub fn try_jump(
+        &mut self,
+        settings: &PlayerControllerSettings,
+        state: PlayerMovementState,
+        now: f64,
+    ) -> bool {
+        let buffered = self
+            .jump_requested_at
+            .map_or(false, |t| now - t <= settings.jump_buffer_time as f64);
+
+        if buffered && self.coyote(settings, state, now) {
+            self.jump_requested_at = None;
+            self.last_grounded_at = None;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Consumes a buffered jump request while touching a wall in the air.
+    pub fn try_wall_jump(&mut self, settings: &PlayerControllerSettings, now: f64) -> bool {
+        let buffered = self
+            .jump_requested_at
+            .map_or(false, |t| now - t <= settings.jump_buffer_time as f64);
+        if buffered {
+            self.jump_requested_at = None;
+        }
+        buffered
+    }
+
+    /// Moves the goal velocity towards the input and returns the acceleration
+    /// along the ground plane needed to reach it this frame. On `steep` ground
+    /// the slide down the slope is not braked.
+    pub fn update(
+        &mut self,
+        settings: &PlayerControllerSettings,
+        input: Vec3,
+        velocity: Vec3,
+        ground_normal: Vec3,
+        steep: bool,
+        delta: f32,
+    ) -> Vec3 {
+        let target = input * settings.max_speed;
+        let diff = target - self.goal_velocity;
+        let max_step = settings.acceleration * delta;
+        self.goal_velocity += diff.clamp_length_max(max_step);
+
+        let planar_velocity = project_on_plane(velocity, ground_normal);
+        let mut error = self.goal_velocity - planar_velocity;
+        if steep {
+            let downhill = project_on_plane(-Vec3::Y, ground_normal).normalize_or_zero();
+            error -= downhill * error.dot(downhill).min(0.0);
+        }
+        (error / delta).clamp_length_max(settings.max_accel_force)
+    }
+}
+
+pub fn project_on_plane(v: Vec3, normal: Vec3) -> Vec3 {
+    v - normal * v.dot(normal)
+}
+
+/// Projects planar input onto the ground, keeping its magnitude. On slopes
+/// steeper than `max_slope_angle` the uphill part of the input is removed.
+pub fn ground_movement(input: Vec3, ground_normal: Vec3, steep: bool) -> Vec3 {
+    let mut movement = project_on_plane(input, ground_normal).normalize_or_zero() * input.length();
+    if steep {
+        let downhill = project_on_plane(-Vec3::Y, ground_normal).normalize_or_zero();
+        let uphill = movement.dot(downhill);
+        if uphill < 0.0 {
+            movement -= downhill * uphill;
+        }
+    }
+    movement
+}
+
+pub fn spawn_camera(commands: &mut Commands, target: Entity) -> Entity {
     commands
         .spawn_bundle(PerspectiveCameraBundle {
             transform: Transform::from_xyz(-2.0, 2.5, 5.0).looking_at(Vec3::ZERO, Vec3::Y),
             ..Default::default()
         })
         .insert(follower::Follower {
-            id: 0,
             f_type: follower::FollowerType::LookAt,
+            target,
+        })
+        .insert(follower::FollowerController {
+            rotation_speed: 3.0,
+            ..Default::default()
         })
         .insert(follower::FollowerConfig {
             transition_time: 1.5,
@@ -65,15 +595,43 @@ pub fn setup_camera(mut commands: Commands) {
                 to_camera: Vec3::new(1.0, 1.0, 0.0).normalize(),
             },
         })
-        .insert_bundle(PickingCameraBundle::default());
+        .insert_bundle(PickingCameraBundle::default())
+        .id()
 }
 
+/// Spawns the local players side by side, each with its own follower camera.
+/// Only one camera is rendered, bevy 0.6 cannot split the window between them.
 pub fn setup_player(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    local_players: Res<LocalPlayers>,
 ) {
-    // player
+    for i in 0..local_players.count.max(1) {
+        let player = spawn_player(
+            &mut commands,
+            &mut meshes,
+            &mut materials,
+            Vec3::new(i as f32 * 3.0, 10.0, 0.0),
+        );
+        let camera = spawn_camera(&mut commands, player);
+        commands.entity(player).insert(PlayerCamera(camera));
+        if i > 0 {
+            commands
+                .entity(player)
+                .insert(LocalInputMap(InputMap::local_player(Gamepad(i))));
+        }
+    }
+}
+
+pub fn spawn_player(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    position: Vec3,
+) -> Entity {
+    let settings = PlayerControllerSettings::standard();
+
     commands
         .spawn_bundle(PbrBundle {
             mesh: meshes.add(Mesh::from(bevy::prelude::shape::Box::new(1.0, 2.0, 1.0))),
@@ -85,7 +643,7 @@ pub fn setup_player(
             ..Default::default()
         })
         .insert_bundle(RigidBodyBundle {
-            position: Vec3::new(0.0, 10.0, 0.0).into(),
+            position: position.into(),
             // mass_properties: RigidBodyMassPropsComponent(RigidBodyMassProps {
             //     flags: RigidBodyMassPropsFlags::ROTATION_LOCKED,
             //     ..Default::default()
@@ -102,132 +660,602 @@ pub fn setup_player(
             ..Default::default()
         })
         .insert_bundle(ColliderBundle {
-            shape: ColliderShape::capsule(
-                Vec3::new(0.0, 1.0, 0.0).into(),
-                Vec3::new(0.0, -1.0, 0.0).into(),
-                0.5,
-            )
-            .into(),
+            shape: settings.capsule(settings.capsule_half_height).into(),
             // material: ColliderMaterial {
             //     friction: 20.0,
             //     restitution: 0.7,
             //     ..Default::default()
             // }
             // .into(),
-            // flags: ColliderFlagsComponent(ColliderFlags {
-            //     active_events: ActiveEvents::INTERSECTION_EVENTS,
-            //     ..Default::default()
-            // }),
+            // intersection events tell when the player enters water volumes and
+            // checkpoints, contact events when it touches hazards
+            flags: ColliderFlagsComponent(ColliderFlags {
+                active_events: ActiveEvents::INTERSECTION_EVENTS | ActiveEvents::CONTACT_EVENTS,
+                ..Default::default()
+            }),
             // mass_properties: ColliderMassPropsComponent(ColliderMassProps::Density(0.5)),
             ..Default::default()
         })
-        .insert(RigidBodyPositionSync::Discrete)
+        .insert(InterpolatedTransform::default())
+        .insert(Health::new(100.0))
+        .insert(RespawnPoint(position))
         .insert(follower::FollowerTarget)
         .insert(Player)
-        .insert(PlayerControllerSettings {
-            max_speed: 10.0,
-            acceleration: 10.0,
-            max_accel_force: 10.0,
-            ride_height: 2.0,
-            force_str: 10.0,
-            spring_str: 10.0,
-            spring_damper: 1.0,
-            upright_spring_str: 20.0,
-            upright_spring_damper: 5.0,
-            rotate_str: 10.0,
-            jump_str: 10.0,
+        .insert(PlayerMovement::default())
+        .insert(PlayerMovementState::default())
+        .insert(PlayerDash::default())
+        .insert(PlayerInputLatch::default())
+        .insert(PlayerIntent::default())
+        .insert(GroundProbe::default())
+        .insert(PlayerStance {
+            collider_half_height: settings.capsule_half_height,
+            collider_radius: settings.capsule_radius,
+            ..Default::default()
         })
+        .insert(CeilingProbe::default())
+        .insert(WallProbe::default())
+        .insert(LedgeProbe::default())
+        .insert(InWater::default())
+        .insert(settings)
         .insert_bundle(PickableBundle::default())
-        // ray caster
-        .with_children(|command| {
-            command
-                .spawn()
-                .insert(GlobalTransform::default())
-                // originally ray points to -z direction
-                // so we rotate it 90 degrees
-                .insert(Transform::from_rotation(Quat::from_rotation_x(
-                    -90.0_f32.to_radians(),
-                )))
-                .insert(RayCastSource::<Player>::new_transform_empty());
-        });
+        .id()
 }
 
-pub fn apply_forces(
-    keys: Res<Input<KeyCode>>,
-    mut rigid_bodies: Query<
+/// The physics position, the Transform is interpolated for rendering.
+fn physics_transform(rb_pos: &RigidBodyPositionComponent) -> Transform {
+    Transform::from_translation(rb_pos.position.translation.vector.into())
+        .with_rotation(rb_pos.position.rotation.into())
+}
+
+#[allow(clippy::type_complexity)]
+pub fn read_player_intent(
+    step: Res<FixedStep>,
+    input: ActionInput,
+    mut players: Query<
         (
+            &mut PlayerIntent,
+            &mut PlayerInputLatch,
+            &mut PlayerMovement,
             &mut RigidBodyForcesComponent,
-            &mut RigidBodyVelocityComponent,
+            &RigidBodyPositionComponent,
+            Option<&PlayerCamera>,
+            Option<&LocalInputMap>,
+        ),
+        With<Player>,
+    >,
+    cameras: Query<&follower::FollowerPosition, With<Camera>>,
+) {
+    for (mut intent, mut latch, mut player_movement, mut rb_forces, rb_pos, player_camera, map) in
+        players.iter_mut()
+    {
+        let input = input.mapped(map);
+        // without a camera the player moves relative to where it is facing
+        let player_forward = physics_transform(rb_pos).rotation.mul_vec3(Vec3::X);
+        let view = match player_camera.and_then(|c| cameras.get(c.0).ok()) {
+            Some(position) => -position.current_position.to_camera,
+            None => player_forward,
+        };
+        let forward = Vec3::new(view.x, 0.0, view.z)
+            .try_normalize()
+            .unwrap_or(Vec3::X);
+        let right = forward.cross(Vec3::Y);
+
+        // swim along the camera direction, jump and crouch move straight up and down
+        let swim_forward = view.try_normalize().unwrap_or(forward);
+        let vertical = input.pressed(Action::Jump) as i32 - input.pressed(Action::Crouch) as i32;
+        let swim_movement =
+            (input.movement(swim_forward, right) + Vec3::Y * vertical as f32).clamp_length_max(1.0);
+
+        *intent = PlayerIntent {
+            movement: input.movement(forward, right),
+            swim_movement,
+            forward,
+            jump: std::mem::take(&mut latch.jump),
+            jump_released: std::mem::take(&mut latch.jump_released),
+            dash: std::mem::take(&mut latch.dash),
+            crouch: input.pressed(Action::Crouch),
+            sprint: input.pressed(Action::Sprint),
+        };
+        if intent.jump {
+            player_movement.jump_requested_at = Some(step.elapsed);
+        }
+        // the systems after this one add their forces onto it
+        rb_forces.force = Vector::zeros();
+    }
+}
+
+/// Buoyancy, drag and steering along the camera inside water volumes.
+#[allow(clippy::type_complexity)]
+pub fn swim(
+    step: Res<FixedStep>,
+    mut state_events: EventWriter<PlayerMovementStateChanged>,
+    water_volumes: Query<&WaterVolume>,
+    mut players: Query<
+        (
+            Entity,
+            &PlayerControllerSettings,
+            &PlayerIntent,
+            &InWater,
+            &mut PlayerMovement,
+            &mut PlayerMovementState,
+            &mut RigidBodyForcesComponent,
+            &RigidBodyVelocityComponent,
             &RigidBodyMassPropsComponent,
+        ),
+        With<Player>,
+    >,
+) {
+    if step.dt <= 0.0 {
+        return;
+    }
+
+    for (
+        entity,
+        settings,
+        intent,
+        in_water,
+        mut player_movement,
+        mut state,
+        mut rb_forces,
+        rb_vel,
+        rb_mprops,
+    ) in players.iter_mut()
+    {
+        let water = match in_water.volume().and_then(|v| water_volumes.get(v).ok()) {
+            Some(water) => water,
+            None => continue,
+        };
+        player_movement.ledge = None;
+        player_movement.wall = None;
+        state.set(PlayerMovementState::Swimming, entity, &mut state_events);
+
+        let vel = Vec3::from(rb_vel.linvel);
+        let buoyancy = Vec3::Y * water.buoyancy - vel * water.drag;
+        let swim_settings = PlayerControllerSettings {
+            max_speed: settings.swim_speed,
+            acceleration: settings.swim_acceleration,
+            ..settings.clone()
+        };
+        // a zero normal keeps the full 3D velocity
+        let accel = player_movement.update(
+            &swim_settings,
+            intent.swim_movement,
+            vel,
+            Vec3::ZERO,
+            false,
+            step.dt,
+        );
+        rb_forces.force += Vector::from((buoyancy + accel) * rb_mprops.mass());
+    }
+}
+
+/// Grabs ledges while falling past them, hangs from them and climbs onto them.
+/// Hanging and climbing drive the velocity directly.
+#[allow(clippy::type_complexity)]
+pub fn grab_ledges(
+    mut state_events: EventWriter<PlayerMovementStateChanged>,
+    mut players: Query<
+        (
+            Entity,
             &PlayerControllerSettings,
-            &Transform,
+            &PlayerStance,
+            &PlayerIntent,
+            &PlayerDash,
+            &GroundProbe,
+            &LedgeProbe,
+            &mut PlayerMovement,
+            &mut PlayerMovementState,
+            &mut RigidBodyVelocityComponent,
+            &RigidBodyPositionComponent,
         ),
         With<Player>,
     >,
-    camera: Query<&follower::FollowerPosition, With<Camera>>,
-    ray: Query<&RayCastSource<Player>>,
 ) {
-    let position = camera.single();
-    let mut forward = -position.current_position.to_camera;
-    forward.y = 0.0;
-    forward = forward.normalize();
-    let right = forward.cross(Vec3::Y);
+    for (
+        entity,
+        settings,
+        stance,
+        intent,
+        dash,
+        probe,
+        ledge_probe,
+        mut player_movement,
+        mut state,
+        mut rb_vel,
+        rb_pos,
+    ) in players.iter_mut()
+    {
+        if *state == PlayerMovementState::Swimming {
+            continue;
+        }
+        let settings = &stance.apply(settings);
+        let t = physics_transform(rb_pos);
+        let grounded = settings.ground(probe).is_some();
 
-    let mut force = Vec3::default();
-    if keys.pressed(KeyCode::W) {
-        force = forward;
+        // only while falling past the ledge and not dropping down with crouch
+        if player_movement.ledge.is_none()
+            && !grounded
+            && !intent.crouch
+            && !dash.is_dashing()
+            && rb_vel.linvel.y <= 0.0
+        {
+            if let (Some(wall), Some(top)) = (&ledge_probe.wall, &ledge_probe.top) {
+                let chest = t.translation + Vec3::Y * settings.ledge_chest_height;
+                let forward = t.rotation.mul_vec3(Vec3::X);
+                player_movement.ledge = Ledge::detect(settings, chest, forward, wall, top);
+            }
+        }
+
+        // jumping off a ledge is replaced by climbing it
+        let ledge_state = match (*state, player_movement.ledge) {
+            (_, None) => None,
+            (PlayerMovementState::Hanging, Some(_)) if intent.crouch => None,
+            (PlayerMovementState::Hanging, Some(_)) if intent.jump => {
+                player_movement.jump_requested_at = None;
+                Some(PlayerMovementState::Mantling)
+            }
+            (PlayerMovementState::Mantling, Some(ledge)) => {
+                let climbed = t.translation.distance(ledge.climb_position) < 0.2;
+                (!grounded && !climbed).then(|| PlayerMovementState::Mantling)
+            }
+            (_, Some(_)) => Some(PlayerMovementState::Hanging),
+        };
+        let ledge = match (ledge_state, player_movement.ledge) {
+            (Some(ledge_state), Some(ledge)) => {
+                state.set(ledge_state, entity, &mut state_events);
+                ledge
+            }
+            // the movement state moves on from the ledge state
+            _ => {
+                player_movement.ledge = None;
+                continue;
+            }
+        };
+
+        // climbing goes up first
+        let target = match *state {
+            PlayerMovementState::Mantling if t.translation.y < ledge.climb_position.y - 0.1 => {
+                Vec3::new(t.translation.x, ledge.climb_position.y, t.translation.z)
+            }
+            PlayerMovementState::Mantling => ledge.climb_position,
+            _ => ledge.hang_position,
+        };
+        let vel = ((target - t.translation) * settings.ledge_snap_str)
+            .clamp_length_max(settings.ledge_climb_speed);
+        rb_vel.linvel = vel.into();
+        player_movement.goal_velocity = Vec3::ZERO;
     }
-    if keys.pressed(KeyCode::S) {
-        force = -forward;
+}
+
+/// Finds the wall the player presses against in the air, slides down it and
+/// jumps off it.
+#[allow(clippy::type_complexity)]
+pub fn wall_jump(
+    step: Res<FixedStep>,
+    mut state_events: EventWriter<PlayerMovementStateChanged>,
+    mut players: Query<
+        (
+            Entity,
+            &PlayerControllerSettings,
+            &PlayerStance,
+            &PlayerIntent,
+            &GroundProbe,
+            &WallProbe,
+            &mut PlayerMovement,
+            &mut PlayerMovementState,
+            &mut RigidBodyVelocityComponent,
+            &RigidBodyMassPropsComponent,
+        ),
+        With<Player>,
+    >,
+) {
+    let now = step.elapsed;
+    for (
+        entity,
+        settings,
+        stance,
+        intent,
+        probe,
+        wall_probe,
+        mut player_movement,
+        mut state,
+        mut rb_vel,
+        rb_mprops,
+    ) in players.iter_mut()
+    {
+        let settings = &stance.apply(settings);
+        let held = *state == PlayerMovementState::Swimming || player_movement.ledge.is_some();
+        // a wall only counts in the air while the input pushes into it
+        let wall = wall_probe.hit.filter(|hit| {
+            hit.distance <= settings.wall_distance
+                && settings.is_steep(hit.normal)
+                && intent.movement.dot(-hit.normal) > 0.1
+        });
+        player_movement.wall = wall.filter(|_| !held && settings.ground(probe).is_none());
+        let hit = match player_movement.wall {
+            Some(hit) => hit,
+            None => continue,
+        };
+
+        // a ground jump still allowed by the coyote time wins over the wall jump
+        let coyote = player_movement.coyote(settings, *state, now);
+        if !coyote && player_movement.try_wall_jump(settings, now) {
+            state.set(PlayerMovementState::Jumping, entity, &mut state_events);
+            let away = Vec3::new(hit.normal.x, 0.0, hit.normal.z).normalize_or_zero();
+            rb_vel.linvel.y = rb_vel.linvel.y.max(0.0);
+            rb_vel.apply_impulse(
+                rb_mprops,
+                (away * settings.wall_jump_str + Vec3::new(0.0, settings.jump_str, 0.0)).into(),
+            );
+            // steer from the jump direction instead of back into the wall
+            player_movement.goal_velocity = away * settings.max_speed;
+        } else {
+            rb_vel.linvel.y = rb_vel.linvel.y.max(-settings.wall_slide_speed);
+        }
     }
-    if keys.pressed(KeyCode::A) {
-        force = -right;
+}
+
+/// Moves the state on from the ground, wall and vertical velocity, and jumps
+/// off the ground. Left to the swim and ledge systems while they hold the player.
+#[allow(clippy::type_complexity)]
+pub fn update_movement_state(
+    step: Res<FixedStep>,
+    mut state_events: EventWriter<PlayerMovementStateChanged>,
+    mut players: Query<
+        (
+            Entity,
+            &PlayerControllerSettings,
+            &PlayerStance,
+            &GroundProbe,
+            &InWater,
+            &mut PlayerMovement,
+            &mut PlayerMovementState,
+            &mut RigidBodyVelocityComponent,
+            &RigidBodyMassPropsComponent,
+        ),
+        With<Player>,
+    >,
+) {
+    let now = step.elapsed;
+    for (
+        entity,
+        settings,
+        stance,
+        probe,
+        in_water,
+        mut player_movement,
+        mut state,
+        mut rb_vel,
+        rb_mprops,
+    ) in players.iter_mut()
+    {
+        if in_water.volume().is_some() || player_movement.ledge.is_some() {
+            continue;
+        }
+        let settings = &stance.apply(settings);
+        let ground = settings.ground(probe);
+        // steep slopes hold the player up but do not allow jumping
+        if ground.map_or(false, |hit| !settings.is_steep(hit.normal)) {
+            player_movement.last_grounded_at = Some(now);
+        }
+
+        let jumped = player_movement.try_jump(settings, *state, now);
+        let on_wall = player_movement.wall.is_some();
+        let next = state.next(ground.is_some(), on_wall, rb_vel.linvel.y, jumped);
+        state.set(next, entity, &mut state_events);
+        if jumped {
+            rb_vel.apply_impulse(rb_mprops, Vec3::new(0.0, settings.jump_str, 0.0).into());
+        }
     }
-    if keys.pressed(KeyCode::D) {
-        force = right;
+}
+
+#[allow(clippy::type_complexity)]
+pub fn dash(
+    step: Res<FixedStep>,
+    mut dash_started: EventWriter<DashStarted>,
+    mut dash_ended: EventWriter<DashEnded>,
+    mut players: Query<
+        (
+            Entity,
+            &PlayerControllerSettings,
+            &PlayerStance,
+            &PlayerIntent,
+            &GroundProbe,
+            &mut PlayerDash,
+            &mut RigidBodyVelocityComponent,
+            &RigidBodyMassPropsComponent,
+        ),
+        With<Player>,
+    >,
+) {
+    let now = step.elapsed;
+    for (entity, settings, stance, intent, probe, mut dash, mut rb_vel, rb_mprops) in
+        players.iter_mut()
+    {
+        let settings = &stance.apply(settings);
+        let walkable = settings
+            .ground(probe)
+            .map_or(false, |hit| !settings.is_steep(hit.normal));
+        if dash.update(settings, walkable, now) {
+            dash_ended.send(DashEnded { player: entity });
+        }
+        if intent.dash && dash.try_dash(settings, now) {
+            dash_started.send(DashStarted {
+                player: entity,
+                direction: intent.forward,
+            });
+            // start level so air dashes do not inherit the fall speed
+            rb_vel.linvel.y = rb_vel.linvel.y.max(0.0);
+            rb_vel.apply_impulse(rb_mprops, (intent.forward * settings.dash_str).into());
+        }
     }
-    let mut jump = false;
-    if keys.just_pressed(KeyCode::Space) {
-        jump = true
+}
+
+/// Hover spring, gravity, slope sliding, jump cut, steering on the ground and
+/// the torques keeping the player upright and facing the camera.
+#[allow(clippy::type_complexity)]
+pub fn apply_forces(
+    step: Res<FixedStep>,
+    mut rigid_bodies: Query<
+        (
+            &mut RigidBodyForcesComponent,
+            &mut RigidBodyVelocityComponent,
+            &RigidBodyMassPropsComponent,
+            &RigidBodyPositionComponent,
+            &PlayerControllerSettings,
+            &PlayerStance,
+            &PlayerIntent,
+            &PlayerDash,
+            &GroundProbe,
+            &mut PlayerMovement,
+            &PlayerMovementState,
+        ),
+        With<Player>,
+    >,
+    mut ground_bodies: Query<
+        (
+            &mut RigidBodyForcesComponent,
+            &mut RigidBodyActivationComponent,
+            &RigidBodyMassPropsComponent,
+            &RigidBodyTypeComponent,
+            &RigidBodyVelocityComponent,
+            Option<&SurfaceVelocity>,
+        ),
+        Without<Player>,
+    >,
+) {
+    let delta = step.dt;
+    if delta <= 0.0 {
+        return;
     }
 
-    for (mut rb_forces, mut rb_vel, rb_mprops, settings, t) in rigid_bodies.iter_mut() {
-        let ray = ray.single();
-        if let Some(top_intersection) = ray.intersect_top() {
-            let vel = rb_vel.deref().linvel;
-            let ray_dir = ray.ray().unwrap().direction();
+    for (
+        mut rb_forces,
+        mut rb_vel,
+        rb_mprops,
+        rb_pos,
+        settings,
+        stance,
+        intent,
+        dash,
+        probe,
+        mut player_movement,
+        state,
+    ) in rigid_bodies.iter_mut()
+    {
+        let settings = &stance.apply(settings);
+        let t = physics_transform(rb_pos);
+        let ground = settings.ground(probe);
+        let grounded = ground.is_some();
+        let ground_normal = match &ground {
+            Some(hit) => hit.normal,
+            None => Vec3::Y,
+        };
+        let steep = settings.is_steep(ground_normal);
+
+        // Velocity of the ground under the player, so it is carried by moving platforms
+        let (ground_velocity, ground_angvel) = ground
+            .as_ref()
+            .and_then(|hit| {
+                let (_, _, mprops, _, vel, surface) = ground_bodies.get(hit.entity).ok()?;
+                let linvel = platform::point_velocity(vel, mprops, surface, hit.point);
+                Some((linvel, Vec3::from(vel.angvel)))
+            })
+            .unwrap_or((Vec3::ZERO, Vec3::ZERO));
 
-            let relative_vel = ray_dir.dot(vel.into());
+        // the spring only holds the player while grounded, so it does not fight the jump or dash
+        let on_ledge = player_movement.ledge.is_some();
+        let swimming = *state == PlayerMovementState::Swimming;
+        let spring_hit = match &ground {
+            Some(hit)
+                if *state != PlayerMovementState::Jumping
+                    && !dash.is_dashing()
+                    && !on_ledge
+                    && !swimming =>
+            {
+                Some(hit)
+            }
+            _ => None,
+        };
+        if let Some(hit) = spring_hit {
+            let vel: Vec3 = rb_vel.deref().linvel.into();
+            let ray_dir = -Vec3::Y;
 
-            let diff = settings.ride_height - top_intersection.1.distance();
+            let relative_vel = ray_dir.dot(vel - ground_velocity);
+
+            let diff = settings.ride_height - hit.distance;
+            let spring_dir = if settings.spring_along_normal {
+                ground_normal
+            } else {
+                Vec3::Y
+            };
             let spring_force =
-                (diff * settings.spring_str + relative_vel * settings.spring_damper) * Vec3::Y;
+                (diff * settings.spring_str + relative_vel * settings.spring_damper) * spring_dir;
             // Floating
-            rb_forces.force = spring_force.into();
+            rb_forces.force += Vector::from(spring_force);
+
+            // Reaction on the body we are standing on
+            if settings.push_ground_bodies {
+                if let Ok((mut forces, mut activation, mprops, body_type, _, _)) =
+                    ground_bodies.get_mut(hit.entity)
+                {
+                    if body_type.0 == RigidBodyType::Dynamic {
+                        forces.apply_force_at_point(
+                            mprops,
+                            (-spring_force).into(),
+                            hit.point.into(),
+                        );
+                        activation.wake_up(true);
+                    }
+                }
+            }
+        }
+
+        // Gravity, suspended during a dash so air dashes stay level
+        if !state.is_grounded() && !dash.is_dashing() && !on_ledge {
+            let mut gravity = settings.gravity;
+            if rb_vel.linvel.y < 0.0 && !swimming {
+                gravity *= settings.fall_gravity_multiplier;
+            }
+            rb_forces.force += Vector::from(Vec3::new(0.0, -gravity * rb_mprops.mass(), 0.0));
+        }
+
+        // Sliding down steep slopes
+        if grounded && steep {
+            let gravity = Vec3::new(0.0, -settings.gravity, 0.0);
+            let slide = project_on_plane(gravity, ground_normal);
+            rb_forces.force += Vector::from(slide * rb_mprops.mass());
+        }
+
+        // Jump cut
+        if intent.jump_released && *state == PlayerMovementState::Jumping && rb_vel.linvel.y > 0.0 {
+            rb_vel.linvel.y *= settings.jump_cut_multiplier;
         }
 
-        // Movement
-        rb_forces.force += Vector::from(force * settings.force_str);
+        // Movement, skipped during a dash since steering would cancel its velocity
+        if !swimming && !dash.is_dashing() && !on_ledge {
+            let vel: Vec3 = Vec3::from(rb_vel.deref().linvel) - ground_velocity;
+            let move_dir = ground_movement(intent.movement, ground_normal, steep);
+            let accel =
+                player_movement.update(settings, move_dir, vel, ground_normal, steep, delta);
+            rb_forces.force += Vector::from(accel * rb_mprops.mass());
+        }
 
-        let player_up = -ray.ray().unwrap().direction();
+        let player_up = t.rotation.mul_vec3(Vec3::Y);
         let rotation = Quat::from_rotation_arc_colinear(player_up, Vec3::Y);
         let (axis, angle) = rotation.to_axis_angle();
-        let ang_vel: Vec3 = rb_vel.deref().angvel.into();
+        let ang_vel: Vec3 = Vec3::from(rb_vel.deref().angvel) - ground_angvel;
         // Staing upright rotation
         let upright_torque =
             axis * angle * settings.upright_spring_str - ang_vel * settings.upright_spring_damper;
 
-        let player_forward = t.rotation.mul_vec3(Vec3::new(1.0, 0.0, 0.0));
-        let rotation = Quat::from_rotation_arc(player_forward, forward);
+        let player_forward = t.rotation.mul_vec3(Vec3::X);
+        let rotation = Quat::from_rotation_arc(player_forward, intent.forward);
         let (axis, angle) = rotation.to_axis_angle();
         // Forward rotation
         let rotate_torque = axis * angle * settings.rotate_str;
 
         // Torque
         rb_forces.torque = (upright_torque + rotate_torque).into();
-
-        if jump {
-            rb_vel.apply_impulse(rb_mprops, Vec3::new(0.0, settings.jump_str, 0.0).into());
-        }
     }
 }
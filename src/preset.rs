@@ -0,0 +1,156 @@
+use std::path::PathBuf;
+
+use bevy::asset::{AssetLoader, LoadContext, LoadedAsset};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::utils::BoxedFuture;
+use serde::{Deserialize, Serialize};
+
+use crate::player::{Player, PlayerControllerSettings};
+
+pub struct PresetPlugin;
+
+impl Plugin for PresetPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<ControllerPreset>()
+            .init_asset_loader::<ControllerPresetLoader>()
+            .add_startup_system(load_presets)
+            .add_system(assign_presets)
+            .add_system(switch_presets)
+            .add_system(apply_presets)
+            .add_system(save_presets);
+    }
+}
+
+/// Presets available to switch between, loaded from `assets/presets`.
+pub const PRESET_NAMES: &[&str] = &["standard", "heavy", "floaty", "snappy"];
+
+#[derive(Debug, Serialize, Deserialize, TypeUuid)]
+#[serde(transparent)]
+#[uuid = "5b0a3f4e-8c1d-4a67-9e2b-3d7f1c9a6e41"]
+pub struct ControllerPreset(pub PlayerControllerSettings);
+
+#[derive(Default)]
+pub struct ControllerPresetLoader;
+
+impl AssetLoader for ControllerPresetLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let preset: ControllerPreset = ron::de::from_bytes(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(preset));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["preset.ron"]
+    }
+}
+
+/// Loaded preset handles, in the order of `PRESET_NAMES`.
+#[derive(Default)]
+pub struct ControllerPresets {
+    pub handles: Vec<Handle<ControllerPreset>>,
+}
+
+/// Index into `ControllerPresets` of the preset the player uses.
+#[derive(Debug, Default, Component)]
+pub struct PlayerPreset {
+    pub index: usize,
+}
+
+fn asset_path(name: &str) -> String {
+    format!("presets/{}.preset.ron", name)
+}
+
+pub fn load_presets(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let handles = PRESET_NAMES
+        .iter()
+        .map(|name| asset_server.load(asset_path(name).as_str()))
+        .collect();
+    commands.insert_resource(ControllerPresets { handles });
+}
+
+pub fn assign_presets(
+    mut commands: Commands,
+    players: Query<Entity, (With<Player>, Without<PlayerPreset>)>,
+) {
+    for player in players.iter() {
+        commands.entity(player).insert(PlayerPreset::default());
+    }
+}
+
+/// Cycles every player to the next preset on F6.
+pub fn switch_presets(keys: Res<Input<KeyCode>>, mut players: Query<&mut PlayerPreset>) {
+    if !keys.just_pressed(KeyCode::F6) {
+        return;
+    }
+    for mut preset in players.iter_mut() {
+        preset.index = (preset.index + 1) % PRESET_NAMES.len();
+        info!("controller preset: {}", PRESET_NAMES[preset.index]);
+    }
+}
+
+/// Copies a preset into the player settings when the player switches to it
+/// and whenever the preset file is loaded or changed on disk.
+pub fn apply_presets(
+    mut events: EventReader<AssetEvent<ControllerPreset>>,
+    assets: Res<Assets<ControllerPreset>>,
+    presets: Option<Res<ControllerPresets>>,
+    mut players: Query<(
+        &PlayerPreset,
+        ChangeTrackers<PlayerPreset>,
+        &mut PlayerControllerSettings,
+    )>,
+) {
+    let presets = match presets {
+        Some(presets) => presets,
+        None => return,
+    };
+    let reloaded: Vec<Handle<ControllerPreset>> = events
+        .iter()
+        .filter_map(|event| match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+                Some(handle.clone())
+            }
+            AssetEvent::Removed { .. } => None,
+        })
+        .collect();
+
+    for (preset, tracker, mut settings) in players.iter_mut() {
+        let handle = match presets.handles.get(preset.index) {
+            Some(handle) => handle,
+            None => continue,
+        };
+        if tracker.is_changed() || reloaded.contains(handle) {
+            if let Some(loaded) = assets.get(handle) {
+                *settings = loaded.0.clone();
+            }
+        }
+    }
+}
+
+/// Writes the settings of the first player, including inspector edits, back
+/// to its preset file on F7. The file watcher then reloads it.
+pub fn save_presets(
+    keys: Res<Input<KeyCode>>,
+    players: Query<(&PlayerPreset, &PlayerControllerSettings)>,
+) {
+    if !keys.just_pressed(KeyCode::F7) {
+        return;
+    }
+    if let Some((preset, settings)) = players.iter().next() {
+        let path = PathBuf::from("assets").join(asset_path(PRESET_NAMES[preset.index]));
+        let result = ron::ser::to_string_pretty(settings, ron::ser::PrettyConfig::new())
+            .map_err(|e| e.to_string())
+            .and_then(|source| std::fs::write(&path, source).map_err(|e| e.to_string()));
+        match result {
+            Ok(()) => info!("saved controller preset to {}", path.display()),
+            Err(e) => error!("failed to save {}: {}", path.display(), e),
+        }
+    }
+}
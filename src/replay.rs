@@ -0,0 +1,239 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use bevy::app::AppExit;
+use bevy::input::InputSystem;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::player::{ControllerTime, PlayerSystem};
+
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ReplaySettings>()
+            .init_resource::<Recorder>()
+            .init_resource::<Replay>()
+            .add_startup_system(start_session)
+            // the replayed delta must be in the clock before anything reads it
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                feed_replay
+                    .after(InputSystem)
+                    .before(PlayerSystem::ControllerTime),
+            )
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                record_frame
+                    .after(InputSystem)
+                    .after(PlayerSystem::ControllerTime),
+            )
+            .add_system_to_stage(CoreStage::PostUpdate, stop_recording);
+    }
+}
+
+/// Session recorded or replayed from startup, set with `--record <path>` or
+/// `--replay <path>`.
+#[derive(Debug, Default)]
+pub struct ReplaySettings {
+    pub record: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+}
+
+impl ReplaySettings {
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Self {
+        let mut settings = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--record" => settings.record = args.next().map(PathBuf::from),
+                "--replay" => settings.replay = args.next().map(PathBuf::from),
+                _ => {}
+            }
+        }
+        settings
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedFrame {
+    pub delta: f32,
+    pub pressed: Vec<KeyCode>,
+}
+
+impl RecordedFrame {
+    /// Sets `keys` to the recorded state, deriving just pressed and just
+    /// released from the state it had before.
+    pub fn apply(&self, keys: &mut Input<KeyCode>) {
+        keys.clear();
+        let held: Vec<KeyCode> = keys.get_pressed().copied().collect();
+        for key in held {
+            if !self.pressed.contains(&key) {
+                keys.release(key);
+            }
+        }
+        for key in &self.pressed {
+            keys.press(*key);
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputRecording {
+    pub frames: Vec<RecordedFrame>,
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        message: String,
+    },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            Self::Parse { path, message } => write!(f, "{}: {}", path.display(), message),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl InputRecording {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ReplayError> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path).map_err(|error| ReplayError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        ron::from_str(&source).map_err(|e| ReplayError::Parse {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ReplayError> {
+        let path = path.as_ref();
+        let source = ron::to_string(self).map_err(|e| ReplayError::Parse {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        })?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|error| ReplayError::Io {
+                path: dir.to_path_buf(),
+                error,
+            })?;
+        }
+        std::fs::write(path, source).map_err(|error| ReplayError::Io {
+            path: path.to_path_buf(),
+            error,
+        })
+    }
+}
+
+/// Recording in progress and where it is saved.
+#[derive(Debug, Default)]
+pub struct Recorder {
+    pub recording: Option<(PathBuf, InputRecording)>,
+}
+
+/// Recording being fed back and the next frame to play.
+#[derive(Debug, Default)]
+pub struct Replay {
+    pub recording: Option<InputRecording>,
+    pub frame: usize,
+}
+
+pub fn start_session(
+    settings: Res<ReplaySettings>,
+    mut recorder: ResMut<Recorder>,
+    mut replay: ResMut<Replay>,
+) {
+    if let Some(path) = &settings.replay {
+        match InputRecording::load(path) {
+            Ok(recording) => {
+                info!(
+                    "replaying {} frames from {}",
+                    recording.frames.len(),
+                    path.display()
+                );
+                replay.recording = Some(recording);
+                replay.frame = 0;
+            }
+            Err(e) => error!("failed to load replay: {}", e),
+        }
+    } else if let Some(path) = &settings.record {
+        info!("recording input to {}, F9 stops", path.display());
+        recorder.recording = Some((path.clone(), InputRecording::default()));
+    }
+}
+
+pub fn feed_replay(
+    mut replay: ResMut<Replay>,
+    mut keys: ResMut<Input<KeyCode>>,
+    mut clock: ResMut<ControllerTime>,
+) {
+    let replay = &mut *replay;
+    let recording = match &replay.recording {
+        Some(recording) => recording,
+        None => return,
+    };
+    match recording.frames.get(replay.frame) {
+        Some(frame) => {
+            frame.apply(&mut keys);
+            clock.manual = true;
+            clock.delta = frame.delta;
+            clock.elapsed += frame.delta as f64;
+            replay.frame += 1;
+        }
+        None => {
+            info!("replay finished");
+            clock.manual = false;
+            replay.recording = None;
+        }
+    }
+}
+
+/// Records the frame with the delta the controller clock runs with.
+pub fn record_frame(
+    clock: Res<ControllerTime>,
+    keys: Res<Input<KeyCode>>,
+    mut recorder: ResMut<Recorder>,
+) {
+    if let Some((_, recording)) = &mut recorder.recording {
+        recording.frames.push(RecordedFrame {
+            delta: clock.delta,
+            pressed: keys.get_pressed().copied().collect(),
+        });
+    }
+}
+
+/// Saves the recording on F9 or when the app exits.
+pub fn stop_recording(
+    keys: Res<Input<KeyCode>>,
+    mut exit: EventReader<AppExit>,
+    mut recorder: ResMut<Recorder>,
+) {
+    let exiting = exit.iter().count() > 0;
+    if !keys.just_pressed(KeyCode::F9) && !exiting {
+        return;
+    }
+    if let Some((path, recording)) = recorder.recording.take() {
+        match recording.save(&path) {
+            Ok(()) => info!(
+                "saved {} recorded frames to {}",
+                recording.frames.len(),
+                path.display()
+            ),
+            Err(e) => error!("failed to save recording: {}", e),
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use std::path::PathBuf;
+
+use bevy::asset::{AssetServerSettings, FileAssetIo};
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use crate::level::Level;
+use crate::platform::{self, PlatformPlugin};
+use crate::water::WaterPlugin;
+
+pub struct ScenePlugin;
+
+impl Plugin for ScenePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(PlatformPlugin)
+            .add_plugin(WaterPlugin)
+            .init_resource::<LevelSettings>()
+            .add_startup_system(setup_light)
+            .add_startup_system(setup_level)
+            .add_startup_system(platform::setup_platforms);
+    }
+}
+
+/// Level file loaded on startup, relative to the asset folder.
+#[derive(Debug)]
+pub struct LevelSettings {
+    pub path: PathBuf,
+}
+
+impl Default for LevelSettings {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("levels/default.ron"),
+        }
+    }
+}
+
+impl LevelSettings {
+    /// Location of the level file, resolved the way the asset server resolves
+    /// asset paths so it does not depend on the working directory.
+    pub fn file_path(&self, assets: &AssetServerSettings) -> PathBuf {
+        FileAssetIo::get_root_path()
+            .join(&assets.asset_folder)
+            .join(&self.path)
+    }
+}
+
+pub fn setup_light(mut commands: Commands) {
+    commands.insert_resource(AmbientLight {
+        color: Color::WHITE,
+        brightness: 0.3,
+    });
+
+    commands.spawn_bundle(DirectionalLightBundle {
+        directional_light: DirectionalLight {
+            illuminance: 20000.0,
+            shadows_enabled: true,
+            ..Default::default()
+        },
+        transform: Transform::from_xyz(10.0, 20.0, 10.0).looking_at(Vec3::ZERO, Vec3::Y),
+        ..Default::default()
+    });
+}
+
+pub fn setup_level(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    settings: Res<LevelSettings>,
+    asset_settings: Res<AssetServerSettings>,
+) {
+    match Level::load(settings.file_path(&asset_settings)) {
+        Ok(level) => level.spawn(&mut commands, &mut meshes, &mut materials),
+        Err(e) => {
+            error!("failed to load level: {}", e);
+            // fall back to a bare ground so the player has something to stand on
+            setup_ground(commands, meshes, materials);
+        }
+    }
+}
+
+pub fn setup_ground(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let size = 50.0;
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(Mesh::from(bevy::prelude::shape::Plane { size })),
+            material: materials.add(StandardMaterial {
+                base_color: Color::rgb(0.3, 0.5, 0.3),
+                perceptual_roughness: 1.0,
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+        .insert_bundle(RigidBodyBundle {
+            body_type: RigidBodyType::Static.into(),
+            ..Default::default()
+        })
+        .insert_bundle(ColliderBundle {
+            // thin slab with its top face at y = 0
+            shape: ColliderShape::cuboid(size / 2.0, 0.1, size / 2.0).into(),
+            position: Vec3::new(0.0, -0.1, 0.0).into(),
+            ..Default::default()
+        })
+        .insert(RigidBodyPositionSync::Discrete)
+        .insert(Name::new("Ground"));
+}
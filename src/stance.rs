@@ -0,0 +1,135 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::fixed_step::FixedStep;
+use crate::ground_probe::CeilingProbe;
+use crate::player::{PlayerControllerSettings, PlayerIntent};
+
+/// Values that change with the player stance.
+#[derive(Debug, Default, Clone, Reflect, Serialize, Deserialize)]
+pub struct MovementProfile {
+    pub ride_height: f32,
+    pub capsule_half_height: f32,
+    pub max_speed: f32,
+    pub acceleration: f32,
+}
+
+impl MovementProfile {
+    pub fn lerp(&self, other: &Self, t: f32) -> Self {
+        let lerp = |a: f32, b: f32| a + (b - a) * t;
+        Self {
+            ride_height: lerp(self.ride_height, other.ride_height),
+            capsule_half_height: lerp(self.capsule_half_height, other.capsule_half_height),
+            max_speed: lerp(self.max_speed, other.max_speed),
+            acceleration: lerp(self.acceleration, other.acceleration),
+        }
+    }
+}
+
+#[derive(Debug, Default, Component)]
+pub struct PlayerStance {
+    /// Blend towards the crouch profile, 0 is standing
+    pub crouch: f32,
+    /// Blend towards the sprint profile, 0 is walking
+    pub sprint: f32,
+    /// Capsule half height the collider was last built with
+    pub collider_half_height: f32,
+    /// Capsule radius the collider was last built with
+    pub collider_radius: f32,
+}
+
+impl PlayerStance {
+    pub fn is_crouching(&self) -> bool {
+        self.crouch > 0.0
+    }
+
+    /// Moves the blends towards the requested stance. Standing up is refused
+    /// while there is not enough room above the player.
+    pub fn update(
+        &mut self,
+        settings: &PlayerControllerSettings,
+        wants_crouch: bool,
+        wants_sprint: bool,
+        ceiling_distance: Option<f32>,
+        delta: f32,
+    ) {
+        let current = self.profile(settings);
+        let standing = settings.base_profile();
+        let headroom = standing.ride_height - current.ride_height
+            + standing.capsule_half_height
+            + settings.capsule_radius;
+        let blocked = ceiling_distance.map_or(false, |d| d < headroom);
+
+        let crouch = wants_crouch || (self.is_crouching() && blocked);
+        let sprint = wants_sprint && !crouch;
+
+        let step = settings.stance_blend_speed * delta;
+        let approach = |value: f32, target: bool| {
+            let target = if target { 1.0 } else { 0.0 };
+            value + (target - value).clamp(-step, step)
+        };
+        self.crouch = approach(self.crouch, crouch);
+        self.sprint = approach(self.sprint, sprint);
+    }
+
+    pub fn profile(&self, settings: &PlayerControllerSettings) -> MovementProfile {
+        settings
+            .base_profile()
+            .lerp(&settings.crouch, self.crouch)
+            .lerp(&settings.sprint, self.sprint)
+    }
+
+    /// Settings with the stance profile applied.
+    pub fn apply(&self, settings: &PlayerControllerSettings) -> PlayerControllerSettings {
+        let profile = self.profile(settings);
+        PlayerControllerSettings {
+            ride_height: profile.ride_height,
+            capsule_half_height: profile.capsule_half_height,
+            max_speed: profile.max_speed,
+            acceleration: profile.acceleration,
+            ..settings.clone()
+        }
+    }
+}
+
+pub fn update_stance(
+    step: Res<FixedStep>,
+    mut players: Query<(
+        &PlayerControllerSettings,
+        &PlayerIntent,
+        &CeilingProbe,
+        &mut PlayerStance,
+    )>,
+) {
+    for (settings, intent, ceiling, mut stance) in players.iter_mut() {
+        stance.update(
+            settings,
+            intent.crouch,
+            intent.sprint,
+            ceiling.distance,
+            step.dt,
+        );
+    }
+}
+
+/// Rebuilds the player capsule when the stance changes its height or the
+/// settings change its size.
+pub fn update_stance_collider(
+    mut players: Query<(
+        &PlayerControllerSettings,
+        &mut PlayerStance,
+        &mut ColliderShapeComponent,
+    )>,
+) {
+    for (settings, mut stance, mut shape) in players.iter_mut() {
+        let half_height = stance.profile(settings).capsule_half_height;
+        if (half_height - stance.collider_half_height).abs() > 0.01
+            || (settings.capsule_radius - stance.collider_radius).abs() > 0.01
+        {
+            *shape = settings.capsule(half_height).into();
+            stance.collider_half_height = half_height;
+            stance.collider_radius = settings.capsule_radius;
+        }
+    }
+}
@@ -0,0 +1,863 @@
+//! Headless app driving the player controller with scripted input and a
+//! fixed frame time, so controller feel can be checked frame by frame.
+
+use bevy::app::{Events, ManualEventReader};
+use bevy::asset::AssetPlugin;
+use bevy::core::DefaultTaskPoolOptions;
+use bevy::ecs::schedule::IntoSystemDescriptor;
+use bevy::prelude::*;
+use bevy::transform::TransformPlugin;
+use bevy::window::Windows;
+use bevy_rapier3d::prelude::*;
+
+use crate::fixed_step::FixedStepPlugin;
+use crate::follower::{
+    FollowCameraPlugin, FollowerController, FollowerPosition, NewPosition, Position, PositionState,
+};
+use crate::health::{Hazard, Health, HealthPlugin, PlayerDamaged, RespawnPoint};
+use crate::input_map::{InputMap, LocalInputMap};
+use crate::level::{
+    Level, LevelCollider, LevelColliderShape, LevelEntity, LevelError, LevelFormat, LevelMaterial,
+    LevelMesh, LevelRigidBody, LevelRigidBodyType, LevelTransform,
+};
+use crate::player::{
+    ControllerTime, DashStarted, LocalPlayers, Player, PlayerCamera, PlayerControllerSettings,
+    PlayerPlugin,
+};
+use crate::replay::{InputRecording, RecordedFrame, Recorder, Replay, ReplayPlugin};
+use crate::scene;
+use crate::stance::PlayerStance;
+use crate::water::WaterVolume;
+
+/// Same as the fixed step, so every update runs exactly one physics step.
+pub const FRAME_TIME: f32 = 1.0 / 60.0;
+
+pub struct Harness {
+    pub app: App,
+    pub player: Entity,
+}
+
+impl Harness {
+    /// Builds the app without window or renderer: physics, the player plugin
+    /// and a flat ground, then runs the startup systems.
+    pub fn new() -> Self {
+        Self::with_scene(scene::setup_ground)
+    }
+
+    /// Same as `new` with `scene` spawning the level instead of the ground.
+    pub fn with_scene<Params>(scene: impl IntoSystemDescriptor<Params>) -> Self {
+        Self::build(scene, 1)
+    }
+
+    /// Same as `new` with `count` local players, `player` is the one reading
+    /// the global input map.
+    pub fn with_local_players(count: usize) -> Self {
+        Self::build(scene::setup_ground, count)
+    }
+
+    fn build<Params>(scene: impl IntoSystemDescriptor<Params>, players: usize) -> Self {
+        let mut app = App::new();
+        DefaultTaskPoolOptions::default().create_default_pools(&mut app.world);
+        app.add_plugin(TransformPlugin)
+            .add_plugin(AssetPlugin)
+            .add_asset::<Mesh>()
+            .add_asset::<StandardMaterial>()
+            .add_asset::<Image>()
+            .init_resource::<Windows>()
+            .init_resource::<Time>()
+            .init_resource::<Input<KeyCode>>()
+            .init_resource::<Input<MouseButton>>()
+            .init_resource::<Input<GamepadButton>>()
+            .init_resource::<Axis<GamepadAxis>>()
+            .init_resource::<InputMap>()
+            .insert_resource(ControllerTime {
+                manual: true,
+                ..Default::default()
+            })
+            .insert_resource(LocalPlayers { count: players })
+            .add_plugin(RapierPhysicsPlugin::<NoUserData>::default())
+            .add_plugin(FixedStepPlugin)
+            .add_plugin(PlayerPlugin)
+            .add_plugin(FollowCameraPlugin)
+            .add_plugin(ReplayPlugin)
+            .add_plugin(HealthPlugin)
+            .add_startup_system(scene);
+
+        app.update();
+        let player = app
+            .world
+            .query_filtered::<Entity, (With<Player>, Without<LocalInputMap>)>()
+            .iter(&app.world)
+            .next()
+            .expect("player spawned on startup");
+
+        Self { app, player }
+    }
+
+    /// Players reading their own input map.
+    pub fn local_players(&mut self) -> Vec<Entity> {
+        self.app
+            .world
+            .query_filtered::<Entity, (With<Player>, With<LocalInputMap>)>()
+            .iter(&self.app.world)
+            .collect()
+    }
+
+    pub fn press(&mut self, key: KeyCode) {
+        self.app
+            .world
+            .get_resource_mut::<Input<KeyCode>>()
+            .unwrap()
+            .press(key);
+    }
+
+    /// Sets a stick axis of a gamepad used by the global input map.
+    pub fn set_axis(&mut self, axis: GamepadAxisType, value: f32) {
+        let gamepad = Gamepad(0);
+        self.app
+            .world
+            .get_resource_mut::<InputMap>()
+            .unwrap()
+            .gamepad = Some(gamepad);
+        self.app
+            .world
+            .get_resource_mut::<Axis<GamepadAxis>>()
+            .unwrap()
+            .set(GamepadAxis(gamepad, axis), value);
+    }
+
+    /// Runs `frames` updates of `FRAME_TIME` each. Presses are only "just"
+    /// pressed during the first of them.
+    pub fn step(&mut self, frames: usize) {
+        for _ in 0..frames {
+            self.update(FRAME_TIME);
+            self.app
+                .world
+                .get_resource_mut::<Input<KeyCode>>()
+                .unwrap()
+                .clear();
+        }
+    }
+
+    /// Feeds a recorded session frame by frame with its recorded delta.
+    pub fn replay(&mut self, recording: &InputRecording) {
+        for frame in &recording.frames {
+            frame.apply(&mut self.app.world.get_resource_mut::<Input<KeyCode>>().unwrap());
+            self.update(frame.delta);
+        }
+    }
+
+    fn update(&mut self, delta: f32) {
+        {
+            let mut clock = self.app.world.get_resource_mut::<ControllerTime>().unwrap();
+            clock.delta = delta;
+            clock.elapsed += delta as f64;
+        }
+        self.app.update();
+    }
+
+    /// Steps until `f` returns true or `max_frames` ran, returns the frames run.
+    pub fn step_until(
+        &mut self,
+        max_frames: usize,
+        mut f: impl FnMut(&Self) -> bool,
+    ) -> Option<usize> {
+        for frame in 1..=max_frames {
+            self.step(1);
+            if f(self) {
+                return Some(frame);
+            }
+        }
+        None
+    }
+
+    /// Physics position, the transform trails it by the interpolation.
+    pub fn position(&self) -> Vec3 {
+        self.app
+            .world
+            .get::<RigidBodyPositionComponent>(self.player)
+            .unwrap()
+            .position
+            .translation
+            .vector
+            .into()
+    }
+
+    pub fn velocity(&self) -> Vec3 {
+        self.app
+            .world
+            .get::<RigidBodyVelocityComponent>(self.player)
+            .unwrap()
+            .linvel
+            .into()
+    }
+
+    pub fn camera(&self) -> Entity {
+        self.app.world.get::<PlayerCamera>(self.player).unwrap().0
+    }
+
+    /// Planar directions the player moves along for forward and right input.
+    pub fn camera_axes(&self) -> (Vec3, Vec3) {
+        let position = self
+            .app
+            .world
+            .get::<FollowerPosition>(self.camera())
+            .unwrap();
+        let view = -position.current_position.to_camera;
+        let forward = Vec3::new(view.x, 0.0, view.z).normalize();
+        (forward, forward.cross(Vec3::Y))
+    }
+
+    pub fn mass(&self) -> f32 {
+        self.app
+            .world
+            .get::<RigidBodyMassPropsComponent>(self.player)
+            .unwrap()
+            .mass()
+    }
+
+    pub fn settings(&self) -> &PlayerControllerSettings {
+        self.app
+            .world
+            .get::<PlayerControllerSettings>(self.player)
+            .unwrap()
+    }
+
+    /// Drops the player from its spawn point and lets the hover spring settle.
+    pub fn settle(&mut self) {
+        self.step(600);
+    }
+}
+
+#[test]
+fn settles_at_ride_height() {
+    let mut harness = Harness::new();
+    harness.settle();
+
+    let ride_height = harness.settings().ride_height;
+    let position = harness.position();
+    assert!(
+        (position.y - ride_height).abs() < 0.25,
+        "player at {} instead of ride height {}",
+        position.y,
+        ride_height
+    );
+    assert!(harness.velocity().y.abs() < 0.5);
+}
+
+#[test]
+fn jump_apex_within_tolerance() {
+    let mut harness = Harness::new();
+    harness.settle();
+
+    let settings = harness.settings();
+    let start = harness.position().y;
+    // the jump impulse sets the vertical speed, the controller gravity slows it down
+    let speed = settings.jump_str / harness.mass();
+    let expected = start + speed * speed / (2.0 * settings.gravity);
+
+    // held for the whole jump so it is not cut
+    harness.press(KeyCode::Space);
+    let mut apex = start;
+    harness.step_until(300, |h| {
+        apex = apex.max(h.position().y);
+        h.velocity().y < 0.0
+    });
+
+    // relative to the jump height, not the height it starts from
+    assert!(
+        (apex - expected).abs() < (expected - start) * 0.1,
+        "jump apex {} expected {}",
+        apex,
+        expected
+    );
+}
+
+#[test]
+fn walks_ten_meters_in_expected_time() {
+    let mut harness = Harness::new();
+    harness.settle();
+
+    let settings = harness.settings();
+    // ramp up to max speed, then cover the rest at max speed
+    let ramp = settings.max_speed / settings.acceleration;
+    let ramp_distance = settings.max_speed * ramp / 2.0;
+    let expected = ramp + (10.0 - ramp_distance) / settings.max_speed;
+
+    let start = harness.position();
+    harness.press(KeyCode::W);
+    let frames = harness
+        .step_until(600, |h| {
+            let offset = h.position() - start;
+            Vec3::new(offset.x, 0.0, offset.z).length() >= 10.0
+        })
+        .expect("player did not walk 10 m");
+
+    let time = frames as f32 * FRAME_TIME;
+    assert!(
+        (time - expected).abs() < 0.2,
+        "walked 10 m in {}s, expected {}s",
+        time,
+        expected
+    );
+}
+
+#[test]
+fn replays_deterministically() {
+    let frames = |count: usize, pressed: &[KeyCode]| {
+        std::iter::repeat(RecordedFrame {
+            delta: FRAME_TIME,
+            pressed: pressed.to_vec(),
+        })
+        .take(count)
+    };
+    let recording = InputRecording {
+        frames: frames(300, &[])
+            .chain(frames(60, &[KeyCode::W]))
+            .chain(frames(30, &[KeyCode::W, KeyCode::Space]))
+            .chain(frames(60, &[KeyCode::D]))
+            .collect(),
+    };
+
+    let mut first = Harness::new();
+    first.replay(&recording);
+    let mut second = Harness::new();
+    second.replay(&recording);
+
+    assert_eq!(first.position(), second.position());
+    assert_eq!(first.velocity(), second.velocity());
+}
+
+#[test]
+fn saved_recording_replays_live_run() {
+    let path = std::env::temp_dir().join(format!("replay_test_{}.ron", std::process::id()));
+
+    let mut live = Harness::new();
+    live.app
+        .world
+        .get_resource_mut::<Recorder>()
+        .unwrap()
+        .recording = Some((path.clone(), InputRecording::default()));
+    live.step(300);
+    live.press(KeyCode::W);
+    live.step(60);
+    live.press(KeyCode::Space);
+    live.step(30);
+    let (_, recording) = live
+        .app
+        .world
+        .get_resource_mut::<Recorder>()
+        .unwrap()
+        .recording
+        .take()
+        .unwrap();
+    recording.save(&path).unwrap();
+    let loaded = InputRecording::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded, recording);
+
+    // played back by the replay plugin, which drives the clock itself
+    let mut replayed = Harness::new();
+    let frames = loaded.frames.len();
+    *replayed.app.world.get_resource_mut::<Replay>().unwrap() = Replay {
+        recording: Some(loaded),
+        frame: 0,
+    };
+    for _ in 0..frames {
+        replayed.app.update();
+    }
+
+    assert_eq!(replayed.position(), live.position());
+    assert_eq!(replayed.velocity(), live.velocity());
+}
+
+fn planar(v: Vec3) -> Vec3 {
+    Vec3::new(v.x, 0.0, v.z)
+}
+
+#[test]
+fn analog_stick_scales_movement() {
+    let mut harness = Harness::new();
+    harness.settle();
+
+    harness.set_axis(GamepadAxisType::LeftStickY, 0.5);
+    harness.step(120);
+
+    let (forward, _) = harness.camera_axes();
+    let velocity = planar(harness.velocity());
+    let expected = 0.5 * harness.settings().max_speed;
+    assert!(
+        (velocity.length() - expected).abs() < 0.5,
+        "half stick moves at {} instead of {}",
+        velocity.length(),
+        expected
+    );
+    assert!(velocity.normalize().dot(forward) > 0.99);
+}
+
+#[test]
+fn keyboard_diagonal_is_normalized() {
+    let mut harness = Harness::new();
+    harness.settle();
+
+    harness.press(KeyCode::W);
+    harness.press(KeyCode::D);
+    harness.step(120);
+
+    let (forward, right) = harness.camera_axes();
+    let velocity = planar(harness.velocity());
+    let max_speed = harness.settings().max_speed;
+    assert!(
+        (velocity.length() - max_speed).abs() < 0.5,
+        "diagonal moves at {} instead of {}",
+        velocity.length(),
+        max_speed
+    );
+    let direction = velocity.normalize();
+    assert!((direction.dot(forward) - std::f32::consts::FRAC_1_SQRT_2).abs() < 0.05);
+    assert!((direction.dot(right) - std::f32::consts::FRAC_1_SQRT_2).abs() < 0.05);
+}
+
+#[test]
+fn right_stick_rotates_camera() {
+    let mut harness = Harness::new();
+    harness.settle();
+    let camera = harness.camera();
+    let (before, _) = harness.camera_axes();
+
+    harness.set_axis(GamepadAxisType::RightStickX, 1.0);
+    harness.step(30);
+
+    let controller = harness.app.world.get::<FollowerController>(camera).unwrap();
+    assert_eq!(controller.yaw, 1.0);
+    assert_eq!(controller.pitch, 0.0);
+    let expected = controller.rotation_speed * 30.0 * FRAME_TIME;
+    let (after, _) = harness.camera_axes();
+    let angle = before.angle_between(after);
+    assert!(
+        (angle - expected).abs() < 0.05,
+        "camera turned {} rad instead of {}",
+        angle,
+        expected
+    );
+}
+
+#[test]
+fn local_players_read_their_own_input() {
+    let mut harness = Harness::with_local_players(2);
+    harness.settle();
+    let second = harness.local_players()[0];
+    let first_camera = harness.camera();
+    let start = harness.position();
+
+    // I and Numpad6 are the forward and camera keys of the second player only
+    harness.press(KeyCode::I);
+    harness.press(KeyCode::Numpad6);
+    harness.step(60);
+
+    let moved = harness.position() - start;
+    assert!(
+        Vec3::new(moved.x, 0.0, moved.z).length() < 0.05,
+        "first player moved by {}",
+        moved
+    );
+    let controller = harness
+        .app
+        .world
+        .get::<FollowerController>(first_camera)
+        .unwrap();
+    assert_eq!(controller.yaw, 0.0);
+
+    harness.player = second;
+    let controller = harness
+        .app
+        .world
+        .get::<FollowerController>(harness.camera())
+        .unwrap();
+    assert_eq!(controller.yaw, 1.0);
+    assert!(harness.velocity().length() > 1.0);
+    assert_ne!(harness.camera(), first_camera);
+}
+
+/// Slab tilted by 50 degrees, rising towards +x under the player spawn point.
+fn setup_steep_ramp(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let rotation = Quat::from_rotation_z(50.0_f32.to_radians());
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Box::new(100.0, 0.2, 40.0))),
+            material: materials.add(StandardMaterial::default()),
+            transform: Transform::from_rotation(rotation),
+            ..Default::default()
+        })
+        .insert_bundle(ColliderBundle {
+            shape: ColliderShape::cuboid(50.0, 0.1, 20.0).into(),
+            position: (Vec3::ZERO, rotation).into(),
+            ..Default::default()
+        });
+}
+
+#[test]
+fn slides_down_steep_slopes() {
+    let mut harness = Harness::with_scene(setup_steep_ramp);
+    assert!(harness.settings().max_slope_angle < 50.0);
+
+    // land on the ramp, then slide without any input
+    harness.step(90);
+    let start = harness.position();
+    harness.step(60);
+
+    let slid = start.x - harness.position().x;
+    assert!(slid > 1.0, "slid {} m down a 50 degree slope", slid);
+}
+
+#[test]
+fn settings_change_rebuilds_collider() {
+    let mut harness = Harness::new();
+    harness.step(1);
+
+    let player = harness.player;
+    let mut settings = harness
+        .app
+        .world
+        .get_mut::<PlayerControllerSettings>(player)
+        .unwrap();
+    settings.capsule_radius = 0.8;
+    settings.capsule_half_height = 1.2;
+    harness.step(1);
+
+    let shape = harness
+        .app
+        .world
+        .get::<ColliderShapeComponent>(player)
+        .unwrap();
+    let capsule = shape.as_capsule().expect("player collider is a capsule");
+    assert!((capsule.radius - 0.8).abs() < 1e-4);
+    assert!((capsule.half_height() - 1.2).abs() < 1e-4);
+}
+
+#[test]
+fn falling_below_kill_plane_respawns_and_snaps_camera() {
+    let mut harness = Harness::new();
+    harness.settle();
+    let player = harness.player;
+    let camera = harness.camera();
+
+    // a camera transition and a crouch in progress are both cut short
+    let world = &mut harness.app.world;
+    let spawn = world.get::<RespawnPoint>(player).unwrap().0;
+    let elapsed = world.get_resource::<ControllerTime>().unwrap().elapsed;
+    let transition = PositionState::Transition(NewPosition {
+        transiton_start: elapsed,
+        position: Position {
+            distance: 10.0,
+            to_camera: Vec3::new(0.0, 1.0, 1.0).normalize(),
+        },
+    });
+    world
+        .get_mut::<FollowerPosition>(camera)
+        .unwrap()
+        .position_state = transition;
+    world.get_mut::<PlayerStance>(player).unwrap().crouch = 1.0;
+    let below = RigidBodyPositionComponent(Vec3::new(0.0, -30.0, 0.0).into());
+    *world.get_mut::<RigidBodyPositionComponent>(player).unwrap() = below;
+    harness.step(1);
+
+    assert!(harness.position().distance(spawn) < 0.1);
+    let world = &harness.app.world;
+    assert_eq!(world.get::<PlayerStance>(player).unwrap().crouch, 0.0);
+    let position = world.get::<FollowerPosition>(camera).unwrap();
+    assert!(matches!(position.position_state, PositionState::Normal));
+    assert_eq!(position.current_position.distance, 10.0);
+    let target = world.get::<Transform>(player).unwrap().translation;
+    let placed = world.get::<Transform>(camera).unwrap().translation;
+    assert!((placed.distance(target) - 10.0).abs() < 0.01);
+}
+
+#[test]
+fn ctrl_chords_do_not_move_the_player() {
+    let mut harness = Harness::new();
+    harness.settle();
+    let start = harness.position();
+
+    // Ctrl+S saves the level, S alone walks backwards
+    harness.press(KeyCode::LControl);
+    harness.press(KeyCode::S);
+    harness.step(60);
+
+    let moved = planar(harness.position() - start);
+    assert!(moved.length() < 0.05, "player moved by {}", moved);
+}
+
+/// One entity of every body type, collider shape and gameplay volume.
+fn round_trip_level() -> Level {
+    let entity = |name: &str, mesh: LevelMesh| LevelEntity {
+        name: name.into(),
+        mesh,
+        transform: LevelTransform::default(),
+        material: LevelMaterial::default(),
+        collider: None,
+        rigid_body: None,
+        water: None,
+        hazard: None,
+        checkpoint: false,
+    };
+    let collider = |shape: LevelColliderShape, sensor: bool| Some(LevelCollider { shape, sensor });
+    let body = |body_type: LevelRigidBodyType, position: Option<Vec3>| {
+        Some(LevelRigidBody {
+            body_type,
+            position,
+        })
+    };
+    let cube = || LevelColliderShape::Cuboid {
+        half_extents: Vec3::new(1.0, 0.5, 2.0),
+    };
+
+    Level {
+        entities: vec![
+            LevelEntity {
+                transform: LevelTransform {
+                    translation: Vec3::new(1.0, -0.5, 3.0),
+                    rotation: Quat::from_rotation_y(0.5),
+                    scale: Vec3::new(2.0, 1.0, 0.5),
+                },
+                material: LevelMaterial {
+                    base_color: Color::rgba(0.2, 0.4, 0.6, 0.5),
+                    perceptual_roughness: 0.3,
+                    metallic: 0.7,
+                    reflectance: 0.25,
+                    alpha_blend: true,
+                    emissive: Color::rgb(1.0, 0.5, 0.0),
+                    unlit: true,
+                    double_sided: true,
+                },
+                collider: collider(cube(), false),
+                rigid_body: body(LevelRigidBodyType::Static, None),
+                ..entity(
+                    "Ground",
+                    LevelMesh::Box {
+                        x: 2.0,
+                        y: 1.0,
+                        z: 4.0,
+                    },
+                )
+            },
+            LevelEntity {
+                collider: collider(LevelColliderShape::Ball { radius: 0.5 }, false),
+                rigid_body: body(LevelRigidBodyType::Dynamic, Some(Vec3::new(0.0, 4.0, 0.0))),
+                ..entity(
+                    "Ball",
+                    LevelMesh::Sphere {
+                        radius: 0.5,
+                        subdivisions: 3,
+                    },
+                )
+            },
+            LevelEntity {
+                collider: collider(cube(), false),
+                rigid_body: body(LevelRigidBodyType::KinematicPositionBased, None),
+                ..entity(
+                    "Elevator",
+                    LevelMesh::Box {
+                        x: 2.0,
+                        y: 1.0,
+                        z: 4.0,
+                    },
+                )
+            },
+            LevelEntity {
+                collider: collider(
+                    LevelColliderShape::Capsule {
+                        half_height: 0.5,
+                        radius: 0.25,
+                    },
+                    false,
+                ),
+                rigid_body: body(LevelRigidBodyType::KinematicVelocityBased, None),
+                ..entity(
+                    "Roller",
+                    LevelMesh::Capsule {
+                        radius: 0.25,
+                        depth: 1.0,
+                    },
+                )
+            },
+            LevelEntity {
+                collider: collider(cube(), true),
+                water: Some(WaterVolume {
+                    buoyancy: 12.0,
+                    drag: 1.5,
+                }),
+                ..entity(
+                    "Pool",
+                    LevelMesh::Box {
+                        x: 2.0,
+                        y: 1.0,
+                        z: 4.0,
+                    },
+                )
+            },
+            LevelEntity {
+                collider: collider(cube(), false),
+                hazard: Some(Hazard { damage: 25.0 }),
+                ..entity(
+                    "Spikes",
+                    LevelMesh::Box {
+                        x: 2.0,
+                        y: 1.0,
+                        z: 4.0,
+                    },
+                )
+            },
+            LevelEntity {
+                collider: collider(cube(), true),
+                checkpoint: true,
+                ..entity("Checkpoint", LevelMesh::Plane { size: 2.0 })
+            },
+        ],
+    }
+}
+
+#[test]
+fn saved_level_loads_unchanged() {
+    let level = round_trip_level();
+    let expected = ron::to_string(&level).unwrap();
+    for extension in ["ron", "json"] {
+        let path = std::env::temp_dir().join(format!(
+            "level_round_trip_{}.{}",
+            std::process::id(),
+            extension
+        ));
+        level.save(&path).unwrap();
+        let loaded = Level::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.entities.len(), level.entities.len());
+        assert_eq!(
+            ron::to_string(&loaded).unwrap(),
+            expected,
+            "{} level",
+            extension
+        );
+    }
+}
+
+#[test]
+fn textured_materials_are_not_saved() {
+    let textured = StandardMaterial {
+        base_color_texture: Some(Handle::default()),
+        ..Default::default()
+    };
+    assert!(LevelMaterial::from_material(&textured).is_none());
+    assert!(LevelMaterial::from_material(&StandardMaterial::default()).is_some());
+}
+
+#[test]
+fn parse_errors_name_the_entry() {
+    let path = std::path::Path::new("broken.ron");
+    let source = r#"[
+        (name: "Ground", mesh: Plane(size: 10.0)),
+        (name: "Box", mesh: Box(x: 1.0, y: 1.0, z: 1.0)),
+        (name: "Broken", mesh: Box(x: "wide", y: 1.0, z: 1.0)),
+    ]"#;
+    match Level::parse(path, LevelFormat::Ron, source) {
+        Err(LevelError::Parse { entry, .. }) => assert_eq!(entry, Some(2)),
+        other => panic!("expected a parse error, got {:?}", other),
+    }
+
+    let path = std::path::Path::new("broken.json");
+    let source = r#"[
+        {"name": "Ground", "mesh": {"Plane": {"size": 10.0}}},
+        {"name": "Broken", "mesh": {"Plane": {"size": 10.0}}, "checkpoint": 1}
+    ]"#;
+    match Level::parse(path, LevelFormat::Json, source) {
+        Err(LevelError::Parse { entry, .. }) => assert_eq!(entry, Some(1)),
+        other => panic!("expected a parse error, got {:?}", other),
+    }
+}
+
+#[test]
+fn invalid_entries_are_named() {
+    let path = std::path::Path::new("invalid.ron");
+    let source = r#"[
+        (name: "Ground", mesh: Plane(size: 10.0)),
+        (name: "Pool", mesh: Box(x: 1.0, y: 1.0, z: 1.0), water: Some((buoyancy: 12.0))),
+    ]"#;
+    match Level::parse(path, LevelFormat::Ron, source) {
+        Err(LevelError::InvalidEntry { entry, name, .. }) => {
+            assert_eq!(entry, 1);
+            assert_eq!(name, "Pool");
+        }
+        other => panic!("expected an invalid entry, got {:?}", other),
+    }
+}
+
+#[test]
+fn dash_reports_its_direction() {
+    let mut harness = Harness::new();
+    harness.settle();
+    let (forward, _) = harness.camera_axes();
+
+    harness.press(KeyCode::E);
+    harness.step(1);
+
+    let events = harness
+        .app
+        .world
+        .get_resource::<Events<DashStarted>>()
+        .unwrap();
+    let started: Vec<&DashStarted> = events.get_reader().iter(events).collect();
+    assert_eq!(started.len(), 1);
+    assert_eq!(started[0].player, harness.player);
+    assert!(
+        started[0].direction.distance(forward) < 0.01,
+        "dashed along {} instead of {}",
+        started[0].direction,
+        forward
+    );
+}
+
+/// Hazard slab with its top face at y = 0 under the player spawn point.
+fn setup_hazard_floor(mut commands: Commands) {
+    commands
+        .spawn_bundle(RigidBodyBundle {
+            body_type: RigidBodyType::Static.into(),
+            ..Default::default()
+        })
+        .insert_bundle(ColliderBundle {
+            shape: ColliderShape::cuboid(5.0, 0.5, 5.0).into(),
+            position: Vec3::new(0.0, -0.5, 0.0).into(),
+            ..Default::default()
+        })
+        .insert(Hazard { damage: 10.0 });
+}
+
+#[test]
+fn touching_a_hazard_reports_the_damage() {
+    let mut harness = Harness::with_scene(setup_hazard_floor);
+    let hazard = harness
+        .app
+        .world
+        .query_filtered::<Entity, With<Hazard>>()
+        .iter(&harness.app.world)
+        .next()
+        .unwrap();
+
+    let mut reader = ManualEventReader::<PlayerDamaged>::default();
+    let mut damaged = Vec::new();
+    harness
+        .step_until(600, |h| {
+            let events = h.app.world.get_resource::<Events<PlayerDamaged>>().unwrap();
+            damaged.extend(reader.iter(events).map(|e| (e.player, e.hazard, e.amount)));
+            !damaged.is_empty()
+        })
+        .expect("player lands on the hazard");
+
+    assert_eq!(damaged, vec![(harness.player, hazard, 10.0)]);
+    let health = harness.app.world.get::<Health>(harness.player).unwrap();
+    assert_eq!(health.current, 90.0);
+}
@@ -0,0 +1,66 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub struct WaterPlugin;
+
+impl Plugin for WaterPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<WaterVolume>()
+            .add_system(track_water_volumes);
+    }
+}
+
+/// Sensor volume the player swims in.
+#[derive(Debug, Clone, Component, Reflect, Serialize, Deserialize)]
+#[reflect(Component)]
+#[serde(default)]
+pub struct WaterVolume {
+    /// Upward acceleration, floats when above the controller gravity
+    pub buoyancy: f32,
+    /// Velocity damping, 1 / seconds
+    pub drag: f32,
+}
+
+impl Default for WaterVolume {
+    fn default() -> Self {
+        Self {
+            buoyancy: 22.0,
+            drag: 2.0,
+        }
+    }
+}
+
+/// Water volumes the entity collider currently intersects.
+#[derive(Debug, Default, Component)]
+pub struct InWater {
+    pub volumes: Vec<Entity>,
+}
+
+impl InWater {
+    /// Most recently entered volume.
+    pub fn volume(&self) -> Option<Entity> {
+        self.volumes.last().copied()
+    }
+}
+
+pub fn track_water_volumes(
+    mut events: EventReader<IntersectionEvent>,
+    volumes: Query<(), With<WaterVolume>>,
+    mut swimmers: Query<&mut InWater>,
+) {
+    for event in events.iter() {
+        let (a, b) = (event.collider1.entity(), event.collider2.entity());
+        for (volume, swimmer) in [(a, b), (b, a)] {
+            if volumes.get(volume).is_err() {
+                continue;
+            }
+            if let Ok(mut in_water) = swimmers.get_mut(swimmer) {
+                in_water.volumes.retain(|v| *v != volume);
+                if event.intersecting {
+                    in_water.volumes.push(volume);
+                }
+            }
+        }
+    }
+}